@@ -0,0 +1,738 @@
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::{BuildHasherDefault, Hasher};
+use std::mem;
+
+use crate::{
+    case_variants, is_word_char, CharClass, MatchKind, Matcher, NfaRunner, Pattern, Slots,
+};
+
+// Matches patterns with backreferences, which no finite automaton can do since a backreference
+// has to match whatever text its group matched. It walks the `Pattern` itself, trying the
+// alternatives and the rounds of a repetition in priority order and going back to the next one
+// when the rest of the pattern fails, the way a backtracking regex engine does. The pattern is
+// flattened into nodes that know their parent, so what comes after a node can be found without
+// recursion, and what is left to try is kept on an explicit stack so long lines can't overflow
+// the call stack. Counted repetitions keep a count instead of being copied as in the nfa.
+//
+// Trying every path takes exponential time in the worst case, so a path is cut off once it
+// enters a node at a position a path entered before and nothing else decides how the rest of
+// the match goes: that path either failed or is still being tried, as when a repetition
+// matches the empty string. What else can decide it are the counts of the repetitions the node
+// is in and the spans of the groups that backreferences in or after it refer to. Where there
+// are none, the nodes entered are kept for the whole line in a bitset, which drops the
+// positions behind the start being tried. Otherwise they are kept with those values for the
+// current start only. The search may still take time and memory that grow faster than the
+// line, so it gives up after a number of steps that grows with the length of the line, or once
+// what it keeps would take more than `MEMORY_LIMIT` bytes.
+pub struct Backtracker {
+    // The nodes of the pattern, each after the nodes inside it, so the root is the last one.
+    nodes: Vec<Node>,
+    // Runs the pattern with any text standing in for every backreference. No match can start
+    // before the leftmost match it finds, and without one there is no match at all.
+    prefilter: NfaRunner,
+    match_kind: MatchKind,
+    // Matches may only start after a non-word character and end before one, as in `NfaRunner`.
+    word: bool,
+    // Number of slots, as in `Matcher::captures_at`. A path keeps them in its registers,
+    // followed by the position each group opened at, and then the count of each repetition
+    // that keeps one.
+    slots: usize,
+    registers: usize,
+    // Set once a search gave up, until `gave_up` is called.
+    gave_up: Cell<bool>,
+}
+
+// Number of steps a search may take on any line, and how many more it may take for every byte
+// of the line from where the prefilter found a match.
+const STEP_LIMIT: usize = 1 << 22;
+const STEPS_PER_BYTE: usize = 64;
+
+// Number of bytes what a search keeps of the paths it tried may take.
+const MEMORY_LIMIT: usize = 64 << 20;
+
+struct Node {
+    kind: Kind,
+    parent: Option<usize>,
+    // The node after this one in the sequence it is part of.
+    next: Option<usize>,
+    // The registers that, besides the position, decide how a path entering the node goes on.
+    key: Vec<usize>,
+}
+
+enum Kind {
+    Literal(char),
+    AnyDigit,
+    AnyChar,
+    AnyCharIn(CharClass),
+    AnyCharNotIn(CharClass),
+    Start,
+    End,
+    Sequence(Vec<usize>),
+    Or(Vec<usize>),
+    // The node inside the group, and the group's number.
+    Group(usize, usize),
+    // `OneOrMore` and `KleeneStar` are repetitions without a maximum. Only the ones with a
+    // count to keep, because they have a minimum or a maximum, get a register for it.
+    Repeat {
+        child: usize,
+        min: usize,
+        max: Option<usize>,
+        counter: Option<usize>,
+    },
+    // The group's number, and whether case is ignored.
+    Backref(usize, bool),
+}
+
+impl Kind {
+    fn children(&self) -> &[usize] {
+        match self {
+            Kind::Sequence(children) | Kind::Or(children) => children,
+            Kind::Group(child, _) | Kind::Repeat { child, .. } => std::slice::from_ref(child),
+            _ => &[],
+        }
+    }
+
+    // Whether the node matches a single character. Those nodes either fail or move on, so
+    // they can't go round in a loop, and what follows them is cut off where it has to be.
+    fn is_char(&self) -> bool {
+        matches!(
+            self,
+            Kind::Literal(_)
+                | Kind::AnyDigit
+                | Kind::AnyChar
+                | Kind::AnyCharIn(_)
+                | Kind::AnyCharNotIn(_)
+        )
+    }
+}
+
+// What is left to do, kept on an explicit stack so long lines can't overflow the call stack.
+enum Job {
+    // Matches the node and then whatever follows it.
+    Enter(usize, usize),
+    // Goes on with whatever follows the node, which matched up to the position.
+    Exit(usize, usize),
+    // Sets a register back to the value it had before the path being left changed it.
+    Restore(usize, Option<usize>),
+}
+
+struct GaveUp;
+
+// Buffers for `Backtracker::search`, kept across starts so trying a start that fails right
+// away costs no allocations.
+#[derive(Default)]
+struct Scratch {
+    registers: Slots,
+    stack: Vec<Job>,
+    key: Vec<Option<usize>>,
+}
+
+// The paths already tried, as described on `Backtracker`, and what the search may still spend.
+struct Visited {
+    // One bit for every node at every position, in pages of 64 positions that are only
+    // allocated once a node is entered there. The first page holds the positions from
+    // `64 * first_page`.
+    pages: VecDeque<Option<Box<[u64]>>>,
+    first_page: usize,
+    nodes: usize,
+    // The node, position and key of the paths tried from the current start, for the nodes
+    // with a key. The keys are stored once each and referred to by their index.
+    keyed: HashSet<(usize, usize, usize), BuildHasherDefault<FxHasher>>,
+    keys: HashMap<Box<[Option<usize>]>, usize, BuildHasherDefault<FxHasher>>,
+    // Roughly the bytes the pages and the keyed paths take.
+    page_bytes: usize,
+    keyed_bytes: usize,
+    steps_left: usize,
+}
+
+// The hash used by rustc, much faster than the default for the small keys of `Visited`, which
+// come from the input but only ever from one line.
+#[derive(Default)]
+struct FxHasher {
+    hash: u64,
+}
+
+impl Hasher for FxHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.write_u64(b as u64);
+        }
+    }
+
+    fn write_u64(&mut self, n: u64) {
+        self.hash = (self.hash.rotate_left(5) ^ n).wrapping_mul(0x51_7c_c1_b7_27_22_0a_95);
+    }
+
+    fn write_usize(&mut self, n: usize) {
+        self.write_u64(n as u64);
+    }
+
+    fn finish(&self) -> u64 {
+        self.hash
+    }
+}
+
+impl Visited {
+    fn new(nodes: usize, first: usize, steps: usize) -> Visited {
+        Visited {
+            pages: VecDeque::new(),
+            first_page: first / 64,
+            nodes,
+            keyed: HashSet::default(),
+            keys: HashMap::default(),
+            page_bytes: 0,
+            keyed_bytes: 0,
+            steps_left: steps,
+        }
+    }
+
+    fn step(&mut self) -> Result<(), GaveUp> {
+        self.steps_left = self.steps_left.checked_sub(1).ok_or(GaveUp)?;
+        Ok(())
+    }
+
+    // Marks the path as tried, and returns whether it wasn't before.
+    fn insert(
+        &mut self,
+        node: usize,
+        position: usize,
+        key: &[Option<usize>],
+    ) -> Result<bool, GaveUp> {
+        if key.is_empty() {
+            let index = position / 64 - self.first_page;
+            if self.pages.len() <= index {
+                self.pages.resize(index + 1, None);
+            }
+            if self.pages[index].is_none() {
+                self.page_bytes += self.nodes * mem::size_of::<u64>();
+                self.check_memory()?;
+                self.pages[index] = Some(vec![0; self.nodes].into_boxed_slice());
+            }
+            let bits = &mut self.pages[index].as_mut().unwrap()[node];
+            let mask = 1 << (position % 64);
+            let new = *bits & mask == 0;
+            *bits |= mask;
+            return Ok(new);
+        }
+        // Hash tables keep about as much room spare as they use.
+        let index = match self.keys.get(key) {
+            Some(&index) => index,
+            None => {
+                let index = self.keys.len();
+                self.keys.insert(key.into(), index);
+                self.keyed_bytes +=
+                    2 * mem::size_of::<(Box<[Option<usize>]>, usize)>() + mem::size_of_val(key);
+                index
+            }
+        };
+        if !self.keyed.insert((node, position, index)) {
+            return Ok(false);
+        }
+        self.keyed_bytes += 2 * mem::size_of::<(usize, usize, usize)>();
+        self.check_memory()?;
+        Ok(true)
+    }
+
+    fn check_memory(&self) -> Result<(), GaveUp> {
+        if self.page_bytes + self.keyed_bytes > MEMORY_LIMIT {
+            return Err(GaveUp);
+        }
+        Ok(())
+    }
+
+    // Forgets the keyed paths, which only hold for the start they were tried from, and the
+    // pages of positions before `start`, which no later start can reach.
+    fn start_at(&mut self, start: usize) {
+        if !self.keyed.is_empty() {
+            self.keyed.clear();
+            self.keys.clear();
+            self.keyed_bytes = 0;
+        }
+        while self.first_page < start / 64 {
+            if let Some(Some(_)) = self.pages.pop_front() {
+                self.page_bytes -= self.nodes * mem::size_of::<u64>();
+            }
+            self.first_page += 1;
+        }
+    }
+}
+
+impl Backtracker {
+    pub fn new(
+        mut pattern: Pattern,
+        prefilter: NfaRunner,
+        match_kind: MatchKind,
+        word: bool,
+    ) -> Backtracker {
+        let mut groups = 0;
+        pattern.visit(&mut |pattern| {
+            if let Pattern::Group(_, index, _) = pattern {
+                groups = groups.max(*index);
+            }
+        });
+        let slots = 2 * (groups + 1);
+        let mut flattener = Flattener {
+            nodes: vec![],
+            registers: slots + groups + 1,
+        };
+        flattener.flatten(pattern, false);
+        let Flattener {
+            mut nodes,
+            registers,
+        } = flattener;
+
+        // The groups that the backreferences inside each node refer to.
+        let mut inside: Vec<Vec<usize>> = vec![];
+        for node in nodes.iter() {
+            let mut groups = match node.kind {
+                Kind::Backref(index, _) => vec![index],
+                _ => vec![],
+            };
+            for &child in node.kind.children() {
+                groups.extend_from_slice(&inside[child]);
+            }
+            groups.sort_unstable();
+            groups.dedup();
+            inside.push(groups);
+        }
+        // From the root down, the groups that the backreferences a path can reach after each
+        // node refer to, and the counters of the repetitions the node is in.
+        let mut after: Vec<Vec<usize>> = vec![vec![]; nodes.len()];
+        let mut counters: Vec<Vec<usize>> = vec![vec![]; nodes.len()];
+        for id in (0..nodes.len()).rev() {
+            let mut later = after[id].clone();
+            let mut enclosing = counters[id].clone();
+            if let Kind::Repeat { counter, .. } = nodes[id].kind {
+                // Another round can follow.
+                later.extend_from_slice(&inside[id]);
+                enclosing.extend(counter);
+            }
+            for &child in nodes[id].kind.children().iter().rev() {
+                after[child] = later.clone();
+                counters[child] = enclosing.clone();
+                if let Kind::Sequence(_) = nodes[id].kind {
+                    later.extend_from_slice(&inside[child]);
+                }
+            }
+        }
+        for (id, node) in nodes.iter_mut().enumerate() {
+            let mut key = counters[id].clone();
+            for &group in inside[id].iter().chain(after[id].iter()) {
+                key.extend([2 * group, 2 * group + 1, slots + group]);
+            }
+            key.sort_unstable();
+            key.dedup();
+            node.key = key;
+        }
+
+        Backtracker {
+            nodes,
+            prefilter,
+            match_kind,
+            word,
+            slots,
+            registers,
+            gave_up: Cell::new(false),
+        }
+    }
+
+    // The slots of the leftmost match starting at or after `at`.
+    fn run(&self, input: &str, at: usize, earliest: bool) -> Option<Slots> {
+        let first = self.prefilter.leftmost_start(input, at)?;
+        let steps = STEP_LIMIT + STEPS_PER_BYTE * (input.len() - first);
+        let mut visited = Visited::new(self.nodes.len(), first, steps);
+        let mut scratch = Scratch::default();
+        let starts = input[first..]
+            .char_indices()
+            .map(|(offset, _)| first + offset)
+            .chain([input.len()]);
+        for start in starts {
+            let word_before = input[..start].chars().next_back().is_some_and(is_word_char);
+            if self.word && word_before {
+                continue;
+            }
+            visited.start_at(start);
+            match self.search(input, start, earliest, &mut visited, &mut scratch) {
+                Ok(Some(slots)) => return Some(slots),
+                Ok(None) => {}
+                Err(GaveUp) => {
+                    self.gave_up.set(true);
+                    return None;
+                }
+            }
+        }
+        None
+    }
+
+    // The match starting at `start`, if there is one.
+    fn search(
+        &self,
+        input: &str,
+        start: usize,
+        earliest: bool,
+        visited: &mut Visited,
+        scratch: &mut Scratch,
+    ) -> Result<Option<Slots>, GaveUp> {
+        let Scratch {
+            registers,
+            stack,
+            key,
+        } = scratch;
+        registers.clear();
+        registers.resize(self.registers, None);
+        registers[0] = Some(start);
+        stack.clear();
+        stack.push(Job::Enter(self.nodes.len() - 1, start));
+        let mut matched: Option<Slots> = None;
+        while let Some(job) = stack.pop() {
+            visited.step()?;
+            let (id, position) = match job {
+                Job::Enter(id, position) => (id, position),
+                Job::Exit(id, position) => {
+                    if let Some(parent) = self.nodes[id].parent {
+                        self.exit(parent, id, position, registers, stack);
+                        continue;
+                    }
+                    let word_after = input[position..].chars().next().is_some_and(is_word_char);
+                    if self.word && word_after {
+                        continue;
+                    }
+                    let mut found = registers[..self.slots].to_vec();
+                    found[1] = Some(position);
+                    if earliest || self.match_kind == MatchKind::LeftmostFirst {
+                        return Ok(Some(found));
+                    }
+                    // Keep trying the remaining paths for a longer match. Of equally long
+                    // ones, the first found has the highest priority and picks the groups.
+                    if matched
+                        .as_ref()
+                        .filter(|best| best[1] >= found[1])
+                        .is_none()
+                    {
+                        matched = Some(found);
+                    }
+                    continue;
+                }
+                Job::Restore(register, value) => {
+                    registers[register] = value;
+                    continue;
+                }
+            };
+            let node = &self.nodes[id];
+            if !node.kind.is_char() {
+                key.clear();
+                key.extend(node.key.iter().map(|&register| registers[register]));
+                if !visited.insert(id, position, key)? {
+                    continue;
+                }
+            }
+            self.enter(id, position, input, registers, stack);
+        }
+        Ok(matched)
+    }
+
+    fn enter(
+        &self,
+        id: usize,
+        position: usize,
+        input: &str,
+        registers: &mut Slots,
+        stack: &mut Vec<Job>,
+    ) {
+        let c = input[position..].chars().next();
+        let after_c = position + c.map_or(0, char::len_utf8);
+        let end = match &self.nodes[id].kind {
+            Kind::Literal(literal) => c.filter(|c| c == literal).map(|_| after_c),
+            Kind::AnyDigit => c.filter(char::is_ascii_digit).map(|_| after_c),
+            Kind::AnyChar => c.map(|_| after_c),
+            Kind::AnyCharIn(chars) => c.filter(|&c| chars.contains(c)).map(|_| after_c),
+            Kind::AnyCharNotIn(chars) => c.filter(|&c| !chars.contains(c)).map(|_| after_c),
+            Kind::Start => Some(position).filter(|&p| p == 0),
+            Kind::End => Some(position).filter(|&p| p == input.len()),
+            Kind::Backref(index, ignore_case) => {
+                match (registers[2 * index], registers[2 * index + 1]) {
+                    (Some(from), Some(to)) => {
+                        matches_again(&input[from..to], &input[position..], *ignore_case)
+                            .map(|len| position + len)
+                    }
+                    // A group that took no part in the match can't be matched again.
+                    _ => None,
+                }
+            }
+            Kind::Sequence(children) => match children.first() {
+                Some(&first) => {
+                    stack.push(Job::Enter(first, position));
+                    None
+                }
+                None => Some(position),
+            },
+            Kind::Or(children) => {
+                // Pushed in reverse so the first alternative is tried first.
+                for &child in children.iter().rev() {
+                    stack.push(Job::Enter(child, position));
+                }
+                None
+            }
+            // The slots are only set once the group matched, so a backreference inside the
+            // group still refers to what it matched in the previous round.
+            Kind::Group(child, index) => {
+                save(registers, stack, self.slots + index, Some(position));
+                stack.push(Job::Enter(*child, position));
+                None
+            }
+            Kind::Repeat { counter, .. } => {
+                if let Some(counter) = *counter {
+                    save(registers, stack, counter, Some(0));
+                }
+                self.repeat(id, 0, position, stack);
+                None
+            }
+        };
+        if let Some(end) = end {
+            stack.push(Job::Exit(id, end));
+        }
+    }
+
+    // Goes on after `id`, inside `parent`, matched up to `position`.
+    fn exit(
+        &self,
+        parent: usize,
+        id: usize,
+        position: usize,
+        registers: &mut Slots,
+        stack: &mut Vec<Job>,
+    ) {
+        match &self.nodes[parent].kind {
+            Kind::Sequence(_) => match self.nodes[id].next {
+                Some(next) => stack.push(Job::Enter(next, position)),
+                None => stack.push(Job::Exit(parent, position)),
+            },
+            Kind::Group(_, index) => {
+                save(registers, stack, 2 * index, registers[self.slots + index]);
+                save(registers, stack, 2 * index + 1, Some(position));
+                stack.push(Job::Exit(parent, position));
+            }
+            Kind::Repeat {
+                min, max, counter, ..
+            } => {
+                let mut count = 0;
+                // Without a maximum, rounds past the minimum are all the same.
+                if let Some(counter) = *counter {
+                    count = registers[counter].unwrap() + 1;
+                    if max.is_none() {
+                        count = count.min(*min);
+                    }
+                    save(registers, stack, counter, Some(count));
+                }
+                self.repeat(parent, count, position, stack);
+            }
+            // Of the nodes with children, only alternations are left.
+            _ => stack.push(Job::Exit(parent, position)),
+        }
+    }
+
+    // Goes on with the repetition `id` after `count` rounds up to `position`: with another
+    // round while one is allowed, and then with what follows it once there were enough.
+    fn repeat(&self, id: usize, count: usize, position: usize, stack: &mut Vec<Job>) {
+        if let Kind::Repeat {
+            child, min, max, ..
+        } = self.nodes[id].kind
+        {
+            if count >= min {
+                stack.push(Job::Exit(id, position));
+            }
+            if max != Some(count) {
+                stack.push(Job::Enter(child, position));
+            }
+        }
+    }
+}
+
+impl Matcher for Backtracker {
+    fn find_at(&self, input: &str, at: usize, earliest: bool) -> Option<(usize, usize)> {
+        let slots = self.run(input, at, earliest)?;
+        Some((slots[0]?, slots[1]?))
+    }
+
+    fn captures_at(&self, input: &str, at: usize) -> Option<Vec<Option<usize>>> {
+        self.run(input, at, false)
+    }
+
+    fn gave_up(&self) -> bool {
+        self.gave_up.take()
+    }
+}
+
+// Builds the nodes of a `Backtracker` from a pattern.
+struct Flattener {
+    nodes: Vec<Node>,
+    // Number of registers so far, as counters are added after the others.
+    registers: usize,
+}
+
+impl Flattener {
+    // Adds the nodes of `pattern` and returns the one for the whole of it. Case is folded
+    // here, as `NfaBuilder` folds it.
+    fn flatten(&mut self, pattern: Pattern, ignore_case: bool) -> usize {
+        let kind = match pattern {
+            Pattern::Literal(c) if ignore_case && !case_variants(c).is_empty() => {
+                Kind::AnyCharIn(CharClass::from_ranges([(c, c)]).case_folded())
+            }
+            Pattern::Literal(c) => Kind::Literal(c),
+            Pattern::AnyDigit => Kind::AnyDigit,
+            Pattern::AnyChar => Kind::AnyChar,
+            Pattern::AnyCharIn(chars) if ignore_case => Kind::AnyCharIn(chars.case_folded()),
+            Pattern::AnyCharIn(chars) => Kind::AnyCharIn(chars),
+            Pattern::AnyCharNotIn(chars) if ignore_case => Kind::AnyCharNotIn(chars.case_folded()),
+            Pattern::AnyCharNotIn(chars) => Kind::AnyCharNotIn(chars),
+            Pattern::Start => Kind::Start,
+            Pattern::End => Kind::End,
+            Pattern::OneOrMore(pattern) => self.repeat(*pattern, 1, None, ignore_case),
+            Pattern::KleeneStar(pattern) => self.repeat(*pattern, 0, None, ignore_case),
+            Pattern::Repeat(pattern, min, max) => self.repeat(*pattern, min, max, ignore_case),
+            Pattern::Sequence(patterns) => {
+                let children: Vec<usize> = patterns
+                    .into_iter()
+                    .map(|pattern| self.flatten(pattern, ignore_case))
+                    .collect();
+                for pair in children.windows(2) {
+                    self.nodes[pair[0]].next = Some(pair[1]);
+                }
+                Kind::Sequence(children)
+            }
+            Pattern::Or(patterns) => Kind::Or(
+                patterns
+                    .into_iter()
+                    .map(|pattern| self.flatten(pattern, ignore_case))
+                    .collect(),
+            ),
+            Pattern::IgnoreCase(pattern) => return self.flatten(*pattern, true),
+            Pattern::Group(pattern, index, _) => {
+                Kind::Group(self.flatten(*pattern, ignore_case), index)
+            }
+            Pattern::Backref(index) => Kind::Backref(index, ignore_case),
+        };
+        let id = self.nodes.len();
+        for &child in kind.children() {
+            self.nodes[child].parent = Some(id);
+        }
+        self.nodes.push(Node {
+            kind,
+            parent: None,
+            next: None,
+            key: vec![],
+        });
+        id
+    }
+
+    fn repeat(
+        &mut self,
+        pattern: Pattern,
+        min: usize,
+        max: Option<usize>,
+        ignore_case: bool,
+    ) -> Kind {
+        let child = self.flatten(pattern, ignore_case);
+        let counter = (min > 0 || max.is_some()).then(|| {
+            self.registers += 1;
+            self.registers - 1
+        });
+        Kind::Repeat {
+            child,
+            min,
+            max,
+            counter,
+        }
+    }
+}
+
+// Sets `register` for the path being tried, and has it restored when going back from it.
+fn save(registers: &mut Slots, stack: &mut Vec<Job>, register: usize, value: Option<usize>) {
+    stack.push(Job::Restore(register, registers[register]));
+    registers[register] = value;
+}
+
+// The length in bytes of the start of `input` that is the same text as `group`, if it starts
+// with it. Ignoring case, characters that are case variants of each other are the same.
+fn matches_again(group: &str, input: &str, ignore_case: bool) -> Option<usize> {
+    if !ignore_case {
+        return input.starts_with(group).then_some(group.len());
+    }
+    let mut chars = input.char_indices();
+    for expected in group.chars() {
+        let (_, c) = chars.next()?;
+        if c != expected && !case_variants(expected).contains(&c) {
+            return None;
+        }
+    }
+    Some(chars.next().map_or(input.len(), |(offset, _)| offset))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cli::test_options;
+    use crate::Grep;
+
+    fn grep(pattern: &str) -> Grep {
+        Grep::new(&test_options(&[pattern])).unwrap()
+    }
+
+    #[test]
+    fn backtracker_matches_backreferences() {
+        assert_eq!(
+            grep(r"(\w+) and \1").find("cat and dog, dog and dog"),
+            Some((13, 24))
+        );
+        assert_eq!(grep(r"^(a*)b\1$").find("aabaab"), None);
+        assert_eq!(grep(r"(a*)b\1$").find("aabaa"), Some((0, 5)));
+        assert_eq!(grep(r"^(a|b)?c\1").find("c"), None);
+        assert_eq!(grep(r"(é)\1").find("xéé"), Some((1, 5)));
+        assert_eq!(grep(r"((\w)\2)+x").find("aabbx"), Some((0, 5)));
+        assert_eq!(grep(r"(a*)*\1b").find("aab"), Some((0, 3)));
+        assert_eq!(grep(r"^(a|b\1)+$").find("aba"), Some((0, 3)));
+        assert_eq!(grep(r"^(a|b\1)+$").find("abab"), None);
+        assert_eq!(grep(r"(a)\1{2,3}").find("baaaaa"), Some((1, 5)));
+        assert_eq!(
+            grep(r#"(?<q>['"])(.*)\1"#).captures(r#"say "hi" 'x'"#),
+            Some(vec![Some((4, 8)), Some((4, 5)), Some((5, 7))])
+        );
+    }
+
+    #[test]
+    fn backtracker_is_bounded_on_long_lines() {
+        let line = format!("{}cb", "a".repeat(600));
+        assert_eq!(grep(r"(a*)\1b").find(&line), Some((601, 602)));
+        let line = format!("{}b", "a".repeat(1600));
+        assert_eq!(grep(r"(a*)\1b").find(&line), Some((0, 1601)));
+        let line = "ab ".repeat(2000);
+        assert_eq!(grep(r"(\w+) \1 x").find(&line), None);
+        let line = format!("xxy{}", "z".repeat(100_000));
+        assert_eq!(grep(r"(x)\1.{1000}").find(&line), Some((0, 1002)));
+
+        // Trying every pair of spans for the two groups takes too long, so it gives up.
+        let half = "a".repeat(2000);
+        let line = format!("{}b {}c", half, half);
+        let grep = grep(r"(.*)(.*)\2\1$");
+        assert_eq!(grep.find(&line), None);
+        assert!(grep.gave_up());
+        assert!(!grep.gave_up());
+    }
+
+    #[test]
+    fn backtracker_follows_options() {
+        let grep_with = |args: &[&str]| Grep::new(&test_options(args)).unwrap();
+
+        assert_eq!(grep_with(&["-i", r"(ab)\1"]).find("xAbaB"), Some((1, 5)));
+        assert_eq!(grep_with(&["-w", r"(a)\1"]).find("aaa aa"), Some((4, 6)));
+        assert_eq!(
+            grep_with(&["--posix", r"(a|ab)\1|abab"]).find("ababx"),
+            Some((0, 4))
+        );
+        assert_eq!(grep_with(&["-G", r"\(.\)\1"]).find("abccd"), Some((2, 4)));
+        assert_eq!(
+            grep_with(&["-e", "(x)", "-e", r"(y)\1"]).find("yyx"),
+            Some((0, 2))
+        );
+    }
+}
@@ -0,0 +1,219 @@
+use crate::class::CharClass;
+use crate::{repeat, sequence_of, ParseError, Pattern, PatternParser};
+
+// Recursive-descent parser for POSIX basic regular expressions, producing the same `Pattern`s
+// as `PatternParser` does for extended ones. The operators are escaped and their bare
+// characters are literals, except for `*`:
+//
+//   alternation   := concatenation ('\|' concatenation)*
+//   concatenation := '^'? repetition*
+//   repetition    := atom ('*' | '\+' | '\?' | '\{' interval '\}')*
+//   atom          := literal | '.' | '$' | escape | class | '\(' alternation '\)'
+//
+// As in extended ones, `\1` to `\9` refer back to the text matched by a group opened before.
+//
+// `^` is only an anchor at the start of an expression and `$` only at its end, and a `*` with
+// nothing before it to repeat is a literal. Bracket expressions and the counts of intervals
+// are read by the wrapped `PatternParser`, which also holds the position in the input.
+pub struct BreParser<'a> {
+    parser: PatternParser<'a>,
+}
+
+impl<'a> BreParser<'a> {
+    pub fn new(input: &'a [char], fold_case: bool) -> BreParser<'a> {
+        BreParser {
+            parser: PatternParser::new(input, fold_case),
+        }
+    }
+
+    pub fn parse(mut self) -> Result<Pattern, ParseError> {
+        self.alternation()
+    }
+
+    // Whether the input continues with the escaped operator `op`.
+    fn at_operator(&self, op: char) -> bool {
+        self.parser.input[self.parser.index..].starts_with(&['\\', op])
+    }
+
+    fn alternation(&mut self) -> Result<Pattern, ParseError> {
+        let mut branches = vec![self.concatenation()?];
+        while self.at_operator('|') {
+            self.parser.index += 2;
+            branches.push(self.concatenation()?);
+        }
+        let mut branches: Vec<Pattern> = branches.into_iter().map(sequence_of).collect();
+        if branches.len() == 1 {
+            Ok(branches.pop().unwrap())
+        } else {
+            Ok(Pattern::Or(branches))
+        }
+    }
+
+    fn concatenation(&mut self) -> Result<Vec<Pattern>, ParseError> {
+        let mut patterns = vec![];
+        if self.parser.peek() == Some('^') {
+            self.parser.index += 1;
+            patterns.push(Pattern::Start);
+        }
+        while self.parser.peek().is_some() {
+            if self.at_operator('|') || (self.at_operator(')') && self.parser.depth > 0) {
+                break;
+            }
+            patterns.push(self.repetition()?);
+        }
+        Ok(patterns)
+    }
+
+    fn repetition(&mut self) -> Result<Pattern, ParseError> {
+        let mut pattern = self.atom()?;
+        loop {
+            pattern = if self.parser.peek() == Some('*') {
+                self.parser.index += 1;
+                Pattern::KleeneStar(Box::new(pattern))
+            } else if self.at_operator('+') {
+                self.parser.index += 2;
+                Pattern::OneOrMore(Box::new(pattern))
+            } else if self.at_operator('?') {
+                self.parser.index += 2;
+                Pattern::Repeat(Box::new(pattern), 0, Some(1))
+            } else if self.at_operator('{') {
+                let open = self.parser.index;
+                let (min, max) = self.interval()?;
+                repeat(pattern, min, max, open)?
+            } else {
+                break;
+            };
+        }
+        Ok(pattern)
+    }
+
+    // Parses `\{n\}`, `\{n,\}`, `\{,m\}` or `\{n,m\}` starting at the `\{` and leaves the index
+    // past the closing `\}`.
+    fn interval(&mut self) -> Result<(usize, Option<usize>), ParseError> {
+        let open = self.parser.index;
+        let mut index = open + 2;
+        let min = self.parser.count(&mut index, open)?;
+        let max = if self.parser.input.get(index) == Some(&',') {
+            index += 1;
+            self.parser.count(&mut index, open)?
+        } else {
+            Some(min.ok_or(ParseError::InvalidInterval(open))?)
+        };
+        if !self.parser.input[index..].starts_with(&['\\', '}']) {
+            return Err(ParseError::InvalidInterval(open));
+        }
+        let min = min.unwrap_or(0);
+        if matches!(max, Some(max) if max < min) {
+            return Err(ParseError::InvalidInterval(open));
+        }
+        self.parser.index = index + 2;
+        Ok((min, max))
+    }
+
+    fn atom(&mut self) -> Result<Pattern, ParseError> {
+        let start = self.parser.index;
+        let current = self.parser.input[start];
+        self.parser.index += 1;
+        let atom = match current {
+            '\\' => {
+                let c = self
+                    .parser
+                    .peek()
+                    .ok_or(ParseError::TrailingBackslash(start))?;
+                self.parser.index += 1;
+                match c {
+                    '(' => {
+                        self.parser.groups += 1;
+                        let index = self.parser.groups;
+                        self.parser.depth += 1;
+                        let inner = self.alternation()?;
+                        if !self.at_operator(')') {
+                            return Err(ParseError::UnmatchedParen(start));
+                        }
+                        self.parser.index += 2;
+                        self.parser.depth -= 1;
+                        Pattern::Group(Box::new(inner), index, None)
+                    }
+                    ')' => return Err(ParseError::UnmatchedParen(start)),
+                    '{' => return Err(ParseError::NothingToRepeat(start, '{')),
+                    'd' => Pattern::AnyDigit,
+                    'w' => Pattern::AnyCharIn(CharClass::word()),
+                    's' => Pattern::AnyCharIn(CharClass::space()),
+                    'D' => Pattern::AnyCharNotIn(CharClass::digit()),
+                    'W' => Pattern::AnyCharNotIn(CharClass::word()),
+                    'S' => Pattern::AnyCharNotIn(CharClass::space()),
+                    '1'..='9' => self.parser.backref(start, c)?,
+                    _ => Pattern::Literal(c),
+                }
+            }
+            '.' => Pattern::AnyChar,
+            '[' => self.parser.bracket_expression(start)?,
+            '$' if self.parser.peek().is_none()
+                || self.at_operator('|')
+                || self.at_operator(')') =>
+            {
+                Pattern::End
+            }
+            _ => Pattern::Literal(current),
+        };
+        Ok(atom)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cli;
+    use crate::Grep;
+
+    fn find(pattern: &str, input: &str) -> Option<(usize, usize)> {
+        let options = cli::test_options(&["-G", pattern]);
+        Grep::new(&options).unwrap().find(input)
+    }
+
+    fn parse_error(pattern: &str) -> ParseError {
+        let chars: Vec<char> = pattern.chars().collect();
+        BreParser::new(&chars, false).parse().unwrap_err()
+    }
+
+    #[test]
+    fn bre_operators_are_escaped() {
+        assert_eq!(find(r"a+(b)?|c{2}", "xa+(b)?|c{2}"), Some((1, 12)));
+        assert_eq!(find(r"\(ab\)\+", "xababa"), Some((1, 5)));
+        assert_eq!(find(r"cat\|dog", "hotdog"), Some((3, 6)));
+        assert_eq!(find(r"x\(\|a\)y", "xy"), Some((0, 2)));
+        assert_eq!(find(r"ba\?d", "bd bad"), Some((0, 2)));
+        assert_eq!(find(r"a\{2,3\}", "aaaa"), Some((0, 3)));
+        assert_eq!(find(r"a\{,2\}b", "aaab"), Some((1, 4)));
+        assert_eq!(find(r"[0-9]*x", "a12x"), Some((1, 4)));
+    }
+
+    #[test]
+    fn bre_anchors_and_stars_depend_on_position() {
+        assert_eq!(find("^ab$", "ab"), Some((0, 2)));
+        assert_eq!(find("a^b$c", "xa^b$c"), Some((1, 6)));
+        assert_eq!(find(r"\(^a\)", "ab"), Some((0, 1)));
+        assert_eq!(find(r"x\|^a", "ba"), None);
+        assert_eq!(find("*a", "b*a"), Some((1, 3)));
+        assert_eq!(find("^*", "*b"), Some((0, 1)));
+        assert_eq!(find(r"\(*\)", "a*"), Some((1, 2)));
+    }
+
+    #[test]
+    fn bre_errors_report_offsets() {
+        assert_eq!(parse_error(r"a\(b"), ParseError::UnmatchedParen(1));
+        assert_eq!(parse_error(r"a\)"), ParseError::UnmatchedParen(1));
+        assert_eq!(parse_error(r"a\{2"), ParseError::InvalidInterval(1));
+        assert_eq!(parse_error(r"a\{3,2\}"), ParseError::InvalidInterval(1));
+        assert_eq!(
+            parse_error(r"\(a\{500\}\)\{500\}"),
+            ParseError::RepetitionTooLarge(12)
+        );
+        assert_eq!(parse_error(r"\{1\}"), ParseError::NothingToRepeat(0, '{'));
+        assert_eq!(parse_error(r"ab\"), ParseError::TrailingBackslash(2));
+        assert_eq!(
+            parse_error(r"a\1\(b\)"),
+            ParseError::InvalidBackReference(1)
+        );
+    }
+}
@@ -0,0 +1,235 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::OnceLock;
+
+// No character past this one has a case mapping.
+const LAST_CASED: char = '\u{1FFFF}';
+
+// A set of characters stored as sorted, non-overlapping and non-adjacent inclusive ranges, so
+// membership is a binary search instead of a scan over every member.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CharClass {
+    ranges: Vec<(char, char)>,
+}
+
+impl CharClass {
+    pub fn new() -> CharClass {
+        CharClass::default()
+    }
+
+    pub fn from_ranges<I: IntoIterator<Item = (char, char)>>(ranges: I) -> CharClass {
+        let mut class = CharClass::new();
+        for (lo, hi) in ranges {
+            class.ranges.push((lo, hi));
+        }
+        class.normalize();
+        class
+    }
+
+    pub fn digit() -> CharClass {
+        CharClass::from_ranges([('0', '9')])
+    }
+
+    pub fn word() -> CharClass {
+        CharClass::from_ranges([('a', 'z'), ('A', 'Z'), ('0', '9'), ('_', '_')])
+    }
+
+    pub fn space() -> CharClass {
+        CharClass::from_ranges([(' ', ' '), ('\t', '\r')])
+    }
+
+    // The POSIX `[:name:]` classes, with their meaning in the C locale.
+    pub fn posix(name: &str) -> Option<CharClass> {
+        let ranges: &[(char, char)] = match name {
+            "alpha" => &[('a', 'z'), ('A', 'Z')],
+            "digit" => &[('0', '9')],
+            "alnum" => &[('a', 'z'), ('A', 'Z'), ('0', '9')],
+            "upper" => &[('A', 'Z')],
+            "lower" => &[('a', 'z')],
+            "space" => &[(' ', ' '), ('\t', '\r')],
+            "blank" => &[(' ', ' '), ('\t', '\t')],
+            "punct" => &[('!', '/'), (':', '@'), ('[', '`'), ('{', '~')],
+            "print" => &[(' ', '~')],
+            "graph" => &[('!', '~')],
+            "cntrl" => &[('\0', '\x1f'), ('\x7f', '\x7f')],
+            "xdigit" => &[('0', '9'), ('a', 'f'), ('A', 'F')],
+            _ => return None,
+        };
+        Some(CharClass::from_ranges(ranges.iter().copied()))
+    }
+
+    pub fn push(&mut self, lo: char, hi: char) {
+        self.ranges.push((lo, hi));
+        self.normalize();
+    }
+
+    pub fn union(&mut self, other: &CharClass) {
+        self.ranges.extend_from_slice(&other.ranges);
+        self.normalize();
+    }
+
+    // Every character that is not in this class.
+    pub fn negated(&self) -> CharClass {
+        let mut ranges = vec![];
+        let mut next = Some('\0');
+        for &(lo, hi) in self.ranges.iter() {
+            if let Some(from) = next {
+                if from < lo {
+                    ranges.push((from, prev_char(lo).unwrap()));
+                }
+            }
+            next = next_char(hi);
+        }
+        if let Some(from) = next {
+            ranges.push((from, char::MAX));
+        }
+        CharClass { ranges }
+    }
+
+    // This class plus every character that is equal to one of its members under Unicode simple
+    // case folding.
+    pub fn case_folded(&self) -> CharClass {
+        let mut folded = self.clone();
+        for &(lo, hi) in self.ranges.iter() {
+            if lo > LAST_CASED {
+                break;
+            }
+            for c in lo..=hi.min(LAST_CASED) {
+                for &variant in case_variants(c) {
+                    folded.ranges.push((variant, variant));
+                }
+            }
+        }
+        folded.normalize();
+        folded
+    }
+
+    pub fn contains(&self, c: char) -> bool {
+        self.ranges
+            .binary_search_by(|&(lo, hi)| {
+                if hi < c {
+                    std::cmp::Ordering::Less
+                } else if lo > c {
+                    std::cmp::Ordering::Greater
+                } else {
+                    std::cmp::Ordering::Equal
+                }
+            })
+            .is_ok()
+    }
+
+    fn normalize(&mut self) {
+        self.ranges.sort_unstable();
+        let mut merged: Vec<(char, char)> = Vec::with_capacity(self.ranges.len());
+        for &(lo, hi) in self.ranges.iter() {
+            match merged.last_mut() {
+                // The last range either reaches char::MAX or ends right before `lo` or later.
+                Some(last) if next_char(last.1).filter(|&next| lo > next).is_none() => {
+                    last.1 = last.1.max(hi);
+                }
+                _ => merged.push((lo, hi)),
+            }
+        }
+        self.ranges = merged;
+    }
+}
+
+impl fmt::Display for CharClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for &(lo, hi) in self.ranges.iter() {
+            if lo == hi {
+                write!(f, "{}", lo.escape_debug())?;
+            } else {
+                write!(f, "{}-{}", lo.escape_debug(), hi.escape_debug())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Every character that folds to the same character as `c`, including `c` itself, or nothing
+// when `c` has no other case.
+pub fn case_variants(c: char) -> &'static [char] {
+    static VARIANTS: OnceLock<HashMap<char, Vec<char>>> = OnceLock::new();
+    let variants = VARIANTS.get_or_init(|| {
+        let mut by_fold: HashMap<char, Vec<char>> = HashMap::new();
+        for c in '\0'..=LAST_CASED {
+            by_fold.entry(simple_fold(c)).or_default().push(c);
+        }
+        let mut variants = HashMap::new();
+        for members in by_fold.into_values().filter(|members| members.len() > 1) {
+            for &member in members.iter() {
+                variants.insert(member, members.clone());
+            }
+        }
+        variants
+    });
+    variants.get(&c).map_or(&[], Vec::as_slice)
+}
+
+// std only offers the full case mappings, which can turn one character into several. Mapping
+// to upper and back to lower case, where both give a single character, brings variants such
+// as 'ſ', 'S' and 's' or 'K' (Kelvin sign), 'K' and 'k' to the same character. The dotless
+// 'ı' only folds to 'i' in Turkic locales, so it is left alone.
+fn simple_fold(c: char) -> char {
+    if c == '\u{131}' {
+        return c;
+    }
+    let upper = single(c.to_uppercase()).unwrap_or(c);
+    single(upper.to_lowercase()).unwrap_or(upper)
+}
+
+fn single<I: Iterator<Item = char>>(mut chars: I) -> Option<char> {
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Some(c),
+        _ => None,
+    }
+}
+
+// The neighbouring scalar values, stepping over the surrogate gap.
+fn next_char(c: char) -> Option<char> {
+    match c {
+        '\u{D7FF}' => Some('\u{E000}'),
+        _ => char::from_u32(c as u32 + 1),
+    }
+}
+
+fn prev_char(c: char) -> Option<char> {
+    match c {
+        '\u{E000}' => Some('\u{D7FF}'),
+        _ => (c as u32).checked_sub(1).and_then(char::from_u32),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ranges_are_merged() {
+        let class = CharClass::from_ranges([('d', 'f'), ('a', 'c'), ('x', 'x'), ('e', 'k')]);
+        assert_eq!(class.to_string(), "a-kx");
+        assert!(class.contains('a') && class.contains('h') && class.contains('x'));
+        assert!(!class.contains('l') && !class.contains('w'));
+    }
+
+    #[test]
+    fn case_folding() {
+        let class = CharClass::from_ranges([('a', 'c'), ('K', 'K'), ('7', '7')]).case_folded();
+        assert_eq!(class.to_string(), "7A-CKa-ck\u{212a}");
+        assert_eq!(case_variants('s'), &['S', 's', '\u{17f}']);
+        assert!(case_variants('-').is_empty());
+    }
+
+    #[test]
+    fn negation_covers_everything_else() {
+        let class = CharClass::from_ranges([('\0', 'a'), ('c', 'c')]).negated();
+        assert!(class.contains('b') && class.contains('d') && class.contains(char::MAX));
+        assert!(!class.contains('\0') && !class.contains('a') && !class.contains('c'));
+        assert_eq!(
+            class.negated(),
+            CharClass::from_ranges([('\0', 'a'), ('c', 'c')])
+        );
+        assert!(CharClass::new().negated().contains('\u{E000}'));
+    }
+}
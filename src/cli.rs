@@ -0,0 +1,706 @@
+use std::fs;
+use std::io;
+use std::io::Read;
+
+use thiserror::Error;
+
+pub const USAGE: &str = "Usage: grep [OPTION]... PATTERNS [FILE]...";
+
+pub const HELP: &str = "\
+Usage: grep [OPTION]... PATTERNS [FILE]...
+Search for PATTERNS in each FILE.
+Example: grep -E 'hello world' menu.h main.c
+
+Pattern selection and interpretation:
+  -E, --extended-regexp     PATTERNS are extended regular expressions
+  -F, --fixed-strings       PATTERNS are strings
+  -G, --basic-regexp        PATTERNS are basic regular expressions
+  -e, --regexp=PATTERNS     use PATTERNS for matching
+  -f, --file=FILE           take PATTERNS from FILE
+  -i, --ignore-case         ignore case distinctions in patterns and data
+  -w, --word-regexp         match only whole words
+  -x, --line-regexp         match only whole lines
+      --posix               report the longest of the leftmost matches
+
+Miscellaneous:
+  -v, --invert-match        select non-matching lines
+      --debug=WHAT          dump internals to stderr; WHAT is 'ast', 'nfa',
+                            'trace' or 'groups' and the option may be
+                            repeated
+      --help                display this help text and exit
+
+Output control:
+  -b, --byte-offset         print the byte offset with output lines
+  -n, --line-number         print line number with output lines
+      --column              print the column of the first match
+      --color[=WHEN]        use markers to highlight the matching strings;
+                            WHEN is 'always', 'never', or 'auto'
+  -o, --only-matching       show only nonempty parts of lines that match
+  -m, --max-count=NUM       stop after NUM selected lines
+  -c, --count               print only a count of selected lines per FILE
+  -l, --files-with-matches  print only names of FILEs with selected lines
+  -L, --files-without-match print only names of FILEs with no selected lines
+  -q, --quiet, --silent     suppress all normal output
+
+Context control:
+  -B, --before-context=NUM  print NUM lines of leading context
+  -A, --after-context=NUM   print NUM lines of trailing context
+  -C, --context=NUM         print NUM lines of output context
+
+File and directory selection:
+  -r, --recursive           search directories recursively
+  -R, --dereference-recursive
+                            likewise, but follow all symlinks
+
+When FILE is '-', read standard input. With no FILE, read '.' if
+recursive, '-' otherwise. Exit status is 0 if any line is selected,
+1 otherwise; if any error occurs, the exit status is 2.
+";
+
+#[derive(Debug, Error, PartialEq)]
+pub enum UsageError {
+    #[error("invalid option -- '{0}'")]
+    UnknownOption(char),
+    #[error("unrecognized option '{0}'")]
+    UnknownLongOption(String),
+    #[error("option '{0}' is ambiguous")]
+    AmbiguousOption(String),
+    #[error("option requires an argument -- '{0}'")]
+    MissingArgument(char),
+    #[error("option '--{0}' requires an argument")]
+    MissingLongArgument(&'static str),
+    #[error("option '--{0}' doesn't allow an argument")]
+    UnexpectedArgument(&'static str),
+    #[error("invalid argument '{value}' for '--{option}'")]
+    InvalidArgument { option: &'static str, value: String },
+    #[error("{0}: invalid context length argument")]
+    InvalidContextLength(String),
+    #[error("invalid max count")]
+    InvalidMaxCount,
+    // Not a usage error as such, but like one it's found before searching starts.
+    #[error("{path}: {message}")]
+    PatternFile { path: String, message: String },
+    #[error("no pattern given")]
+    MissingPattern,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Options {
+    pub patterns: Vec<String>,
+    pub syntax: Syntax,
+    pub paths: Vec<String>,
+    pub recursive: bool,
+    pub follow_links: bool,
+    pub ignore_case: bool,
+    pub word_regexp: bool,
+    pub line_regexp: bool,
+    // Report the leftmost-longest match instead of the leftmost-first one.
+    pub posix: bool,
+    pub invert: bool,
+    pub count: bool,
+    pub line_number: bool,
+    pub column: bool,
+    pub byte_offset: bool,
+    pub only_matching: bool,
+    pub color: ColorChoice,
+    pub list_files: Option<ListFiles>,
+    // Print nothing; only the exit status tells whether a line was selected.
+    pub quiet: bool,
+    pub max_count: Option<u64>,
+    // Lines of context around selected lines. `-A` and `-B` take precedence over `-C`
+    // whichever order they are given in.
+    pub before_context: Option<usize>,
+    pub after_context: Option<usize>,
+    pub context: usize,
+    pub debug: DebugOptions,
+}
+
+// Which internals `--debug` writes to stderr while matching.
+#[derive(Debug, Default, PartialEq)]
+pub struct DebugOptions {
+    // The parsed `Pattern`.
+    pub ast: bool,
+    // The compiled `Nfa`.
+    pub nfa: bool,
+    // The active state set after every character fed to the `NfaRunner`.
+    pub trace: bool,
+    // The spans of the capture groups of the leftmost match in every selected line.
+    pub groups: bool,
+}
+
+// How patterns are read; the last of -E, -F and -G wins.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum Syntax {
+    #[default]
+    Extended,
+    Basic,
+    Fixed,
+}
+
+// When to highlight the output; `Auto` colors it only when writing to a terminal.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum ColorChoice {
+    #[default]
+    Never,
+    Auto,
+    Always,
+}
+
+// Which file names `-l` and `-L` print instead of the selected lines.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ListFiles {
+    WithMatches,
+    WithoutMatch,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Search(Options),
+    Help,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Flag {
+    Extended,
+    FixedStrings,
+    BasicRegexp,
+    Regexp,
+    File,
+    IgnoreCase,
+    WordRegexp,
+    LineRegexp,
+    Posix,
+    InvertMatch,
+    Count,
+    LineNumber,
+    Column,
+    ByteOffset,
+    OnlyMatching,
+    Color,
+    MaxCount,
+    FilesWithMatches,
+    FilesWithoutMatch,
+    Quiet,
+    BeforeContext,
+    AfterContext,
+    Context,
+    Recursive,
+    DereferenceRecursive,
+    Debug,
+    Help,
+}
+
+// Whether an option takes an argument. An optional argument can only be attached, as in
+// `--color=always`, since otherwise it couldn't be told apart from an operand.
+#[derive(Clone, Copy, PartialEq)]
+enum Value {
+    None,
+    Required,
+    Optional,
+}
+
+struct Spec {
+    short: Option<char>,
+    long: &'static str,
+    value: Value,
+    flag: Flag,
+}
+
+const SPECS: &[Spec] = &[
+    Spec {
+        short: Some('E'),
+        long: "extended-regexp",
+        value: Value::None,
+        flag: Flag::Extended,
+    },
+    Spec {
+        short: Some('F'),
+        long: "fixed-strings",
+        value: Value::None,
+        flag: Flag::FixedStrings,
+    },
+    Spec {
+        short: Some('G'),
+        long: "basic-regexp",
+        value: Value::None,
+        flag: Flag::BasicRegexp,
+    },
+    Spec {
+        short: Some('e'),
+        long: "regexp",
+        value: Value::Required,
+        flag: Flag::Regexp,
+    },
+    Spec {
+        short: Some('f'),
+        long: "file",
+        value: Value::Required,
+        flag: Flag::File,
+    },
+    Spec {
+        short: Some('i'),
+        long: "ignore-case",
+        value: Value::None,
+        flag: Flag::IgnoreCase,
+    },
+    Spec {
+        short: Some('w'),
+        long: "word-regexp",
+        value: Value::None,
+        flag: Flag::WordRegexp,
+    },
+    Spec {
+        short: Some('x'),
+        long: "line-regexp",
+        value: Value::None,
+        flag: Flag::LineRegexp,
+    },
+    Spec {
+        short: None,
+        long: "posix",
+        value: Value::None,
+        flag: Flag::Posix,
+    },
+    Spec {
+        short: Some('v'),
+        long: "invert-match",
+        value: Value::None,
+        flag: Flag::InvertMatch,
+    },
+    Spec {
+        short: Some('c'),
+        long: "count",
+        value: Value::None,
+        flag: Flag::Count,
+    },
+    Spec {
+        short: Some('n'),
+        long: "line-number",
+        value: Value::None,
+        flag: Flag::LineNumber,
+    },
+    Spec {
+        short: None,
+        long: "column",
+        value: Value::None,
+        flag: Flag::Column,
+    },
+    Spec {
+        short: Some('b'),
+        long: "byte-offset",
+        value: Value::None,
+        flag: Flag::ByteOffset,
+    },
+    Spec {
+        short: Some('o'),
+        long: "only-matching",
+        value: Value::None,
+        flag: Flag::OnlyMatching,
+    },
+    Spec {
+        short: None,
+        long: "color",
+        value: Value::Optional,
+        flag: Flag::Color,
+    },
+    Spec {
+        short: None,
+        long: "colour",
+        value: Value::Optional,
+        flag: Flag::Color,
+    },
+    Spec {
+        short: Some('m'),
+        long: "max-count",
+        value: Value::Required,
+        flag: Flag::MaxCount,
+    },
+    Spec {
+        short: Some('l'),
+        long: "files-with-matches",
+        value: Value::None,
+        flag: Flag::FilesWithMatches,
+    },
+    Spec {
+        short: Some('L'),
+        long: "files-without-match",
+        value: Value::None,
+        flag: Flag::FilesWithoutMatch,
+    },
+    Spec {
+        short: Some('q'),
+        long: "quiet",
+        value: Value::None,
+        flag: Flag::Quiet,
+    },
+    Spec {
+        short: None,
+        long: "silent",
+        value: Value::None,
+        flag: Flag::Quiet,
+    },
+    Spec {
+        short: Some('B'),
+        long: "before-context",
+        value: Value::Required,
+        flag: Flag::BeforeContext,
+    },
+    Spec {
+        short: Some('A'),
+        long: "after-context",
+        value: Value::Required,
+        flag: Flag::AfterContext,
+    },
+    Spec {
+        short: Some('C'),
+        long: "context",
+        value: Value::Required,
+        flag: Flag::Context,
+    },
+    Spec {
+        short: Some('r'),
+        long: "recursive",
+        value: Value::None,
+        flag: Flag::Recursive,
+    },
+    Spec {
+        short: Some('R'),
+        long: "dereference-recursive",
+        value: Value::None,
+        flag: Flag::DereferenceRecursive,
+    },
+    Spec {
+        short: None,
+        long: "debug",
+        value: Value::Required,
+        flag: Flag::Debug,
+    },
+    Spec {
+        short: None,
+        long: "help",
+        value: Value::None,
+        flag: Flag::Help,
+    },
+];
+
+// Parses the arguments the way getopt_long does for GNU grep: short flags can be combined
+// (`-rE`), a short option's value can be attached (`-efoo`) or follow as the next argument,
+// long options accept `--name=value`, `--name value` or any unambiguous prefix of the name,
+// and options may appear after operands until a `--` ends option processing.
+pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Command, UsageError> {
+    let mut options = Options::default();
+    let mut help = false;
+    // Set by -e and -f even if they add no patterns, as for an empty file.
+    let mut patterns_given = false;
+    let mut operands = vec![];
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            operands.extend(args.by_ref());
+            break;
+        } else if let Some(long) = arg.strip_prefix("--") {
+            let (name, attached) = match long.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (long, None),
+            };
+            let spec = find_long(name)?;
+            let value = match (spec.value, attached) {
+                (Value::Required | Value::Optional, Some(value)) => Some(value),
+                (Value::Required, None) => Some(
+                    args.next()
+                        .ok_or(UsageError::MissingLongArgument(spec.long))?,
+                ),
+                (Value::None, Some(_)) => return Err(UsageError::UnexpectedArgument(spec.long)),
+                (Value::None | Value::Optional, None) => None,
+            };
+            help |= options.apply(spec.flag, value)?;
+            patterns_given |= matches!(spec.flag, Flag::Regexp | Flag::File);
+        } else if arg.len() > 1 && arg.starts_with('-') {
+            for (index, c) in arg.char_indices().skip(1) {
+                let spec = SPECS
+                    .iter()
+                    .find(|spec| spec.short == Some(c))
+                    .ok_or(UsageError::UnknownOption(c))?;
+                let value = if spec.value == Value::Required {
+                    let rest = &arg[index + c.len_utf8()..];
+                    let value = if rest.is_empty() {
+                        args.next().ok_or(UsageError::MissingArgument(c))?
+                    } else {
+                        rest.to_string()
+                    };
+                    Some(value)
+                } else {
+                    None
+                };
+                help |= options.apply(spec.flag, value)?;
+                patterns_given |= matches!(spec.flag, Flag::Regexp | Flag::File);
+                if spec.value == Value::Required {
+                    break;
+                }
+            }
+        } else {
+            operands.push(arg);
+        }
+    }
+
+    if help {
+        return Ok(Command::Help);
+    }
+    let mut operands = operands.into_iter();
+    if !patterns_given {
+        options
+            .patterns
+            .push(operands.next().ok_or(UsageError::MissingPattern)?);
+    }
+    options.paths = operands.collect();
+    Ok(Command::Search(options))
+}
+
+fn find_long(name: &str) -> Result<&'static Spec, UsageError> {
+    if let Some(spec) = SPECS.iter().find(|spec| spec.long == name) {
+        return Ok(spec);
+    }
+    // Prefixes of spellings of the same option, like `--colo`, aren't ambiguous.
+    let first = SPECS.iter().find(|spec| spec.long.starts_with(name));
+    let mut candidates = SPECS.iter().filter(|spec| {
+        spec.long.starts_with(name) && first.filter(|first| first.flag == spec.flag).is_none()
+    });
+    match (first, candidates.next()) {
+        (Some(spec), None) if !name.is_empty() => Ok(spec),
+        (Some(_), Some(_)) => Err(UsageError::AmbiguousOption(format!("--{}", name))),
+        _ => Err(UsageError::UnknownLongOption(format!("--{}", name))),
+    }
+}
+
+impl Options {
+    // Returns true when the flag asks for the help text instead of a search.
+    fn apply(&mut self, flag: Flag, value: Option<String>) -> Result<bool, UsageError> {
+        match flag {
+            Flag::Extended => self.syntax = Syntax::Extended,
+            Flag::FixedStrings => self.syntax = Syntax::Fixed,
+            Flag::BasicRegexp => self.syntax = Syntax::Basic,
+            Flag::Regexp => self.patterns.push(value.unwrap()),
+            Flag::File => {
+                let path = value.unwrap();
+                let contents = read_pattern_file(&path).map_err(|err| UsageError::PatternFile {
+                    message: crate::describe_error(&err),
+                    path,
+                })?;
+                // Every line is a pattern, so an empty file has none and matches nothing. A
+                // final newline ends the last pattern rather than starting an empty one.
+                if !contents.is_empty() {
+                    let contents = contents.strip_suffix('\n').unwrap_or(&contents);
+                    self.patterns
+                        .extend(contents.split('\n').map(str::to_string));
+                }
+            }
+            Flag::IgnoreCase => self.ignore_case = true,
+            Flag::WordRegexp => self.word_regexp = true,
+            Flag::LineRegexp => self.line_regexp = true,
+            Flag::Posix => self.posix = true,
+            Flag::InvertMatch => self.invert = true,
+            Flag::Count => self.count = true,
+            Flag::LineNumber => self.line_number = true,
+            Flag::Column => self.column = true,
+            Flag::ByteOffset => self.byte_offset = true,
+            Flag::OnlyMatching => self.only_matching = true,
+            Flag::Color => {
+                self.color = match value.as_deref() {
+                    None | Some("auto" | "tty" | "if-tty") => ColorChoice::Auto,
+                    Some("always" | "yes" | "force") => ColorChoice::Always,
+                    Some("never" | "no" | "none") => ColorChoice::Never,
+                    Some(_) => {
+                        return Err(UsageError::InvalidArgument {
+                            option: "color",
+                            value: value.unwrap(),
+                        })
+                    }
+                }
+            }
+            Flag::MaxCount => {
+                let value = value.unwrap();
+                self.max_count = Some(value.parse().map_err(|_| UsageError::InvalidMaxCount)?);
+            }
+            Flag::FilesWithMatches => self.list_files = Some(ListFiles::WithMatches),
+            Flag::FilesWithoutMatch => self.list_files = Some(ListFiles::WithoutMatch),
+            Flag::Quiet => self.quiet = true,
+            Flag::BeforeContext => self.before_context = Some(context_length(value.unwrap())?),
+            Flag::AfterContext => self.after_context = Some(context_length(value.unwrap())?),
+            Flag::Context => self.context = context_length(value.unwrap())?,
+            Flag::Recursive => self.recursive = true,
+            Flag::DereferenceRecursive => {
+                self.recursive = true;
+                self.follow_links = true;
+            }
+            Flag::Debug => match value.as_deref() {
+                Some("ast") => self.debug.ast = true,
+                Some("nfa") => self.debug.nfa = true,
+                Some("trace") => self.debug.trace = true,
+                Some("groups") => self.debug.groups = true,
+                _ => {
+                    return Err(UsageError::InvalidArgument {
+                        option: "debug",
+                        value: value.unwrap(),
+                    })
+                }
+            },
+            Flag::Help => return Ok(true),
+        }
+        Ok(false)
+    }
+}
+
+// Reads the file `-f` names, or standard input for `-`.
+fn read_pattern_file(path: &str) -> io::Result<String> {
+    let bytes = if path == "-" {
+        let mut bytes = vec![];
+        io::stdin().read_to_end(&mut bytes)?;
+        bytes
+    } else {
+        fs::read(path)?
+    };
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+fn context_length(value: String) -> Result<usize, UsageError> {
+    value
+        .parse()
+        .map_err(|_| UsageError::InvalidContextLength(value))
+}
+
+// The options `args` parse to, for the tests of every module that need a search set up the
+// way the command line would.
+#[cfg(test)]
+pub fn test_options(args: &[&str]) -> Options {
+    match parse(args.iter().map(|arg| arg.to_string())) {
+        Ok(Command::Search(options)) => options,
+        other => panic!("expected a search for {:?}, got {:?}", args, other),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse_args(args: &[&str]) -> Result<Command, UsageError> {
+        parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn parse_pattern_and_operands() {
+        let options = test_options(&["-E", "a+b", "one.txt", "two.txt"]);
+        assert_eq!(options.patterns, vec!["a+b"]);
+        assert_eq!(options.paths, vec!["one.txt", "two.txt"]);
+        assert!(!options.recursive);
+
+        let options = test_options(&["a+b", "dir", "-rEi"]);
+        assert_eq!(options.patterns, vec!["a+b"]);
+        assert_eq!(options.paths, vec!["dir"]);
+        assert!(options.recursive && options.ignore_case);
+        assert_eq!(options.syntax, Syntax::Extended);
+        assert_eq!(test_options(&["-FG", "a"]).syntax, Syntax::Basic);
+        assert_eq!(test_options(&["-GEF", "a"]).syntax, Syntax::Fixed);
+    }
+
+    #[test]
+    fn parse_repeated_regexp() {
+        let options = test_options(&["-e", "foo", "-ebar", "--regexp=baz", "--regexp", "-x", "f"]);
+        assert_eq!(options.patterns, vec!["foo", "bar", "baz", "-x"]);
+        assert_eq!(options.paths, vec!["f"]);
+    }
+
+    #[test]
+    fn parse_pattern_files() {
+        let path = std::env::temp_dir().join(format!("cli-patterns-{}", std::process::id()));
+        let path_arg = path.to_str().unwrap();
+        fs::write(&path, "foo\n\nba+r\n").unwrap();
+        let options = test_options(&["-e", "x", "-f", path_arg, "in.txt"]);
+        assert_eq!(options.patterns, vec!["x", "foo", "", "ba+r"]);
+        assert_eq!(options.paths, vec!["in.txt"]);
+
+        fs::write(&path, "").unwrap();
+        let options = test_options(&["--file", path_arg, "in.txt"]);
+        assert!(options.patterns.is_empty());
+        assert_eq!(options.paths, vec!["in.txt"]);
+        fs::remove_file(&path).unwrap();
+
+        assert!(matches!(
+            parse_args(&["-f", "/nonexistent/patterns", "a"]),
+            Err(UsageError::PatternFile { message, .. }) if message == "No such file or directory"
+        ));
+    }
+
+    #[test]
+    fn parse_long_options_and_double_dash() {
+        let options = test_options(&["--deref", "--", "-pattern", "--recursive"]);
+        assert!(options.recursive && options.follow_links);
+        assert_eq!(options.patterns, vec!["-pattern"]);
+        assert_eq!(options.paths, vec!["--recursive"]);
+        assert_eq!(parse_args(&["--he"]), Ok(Command::Help));
+    }
+
+    #[test]
+    fn parse_context_lengths() {
+        let options = test_options(&["-A1", "-C", "3", "a"]);
+        assert_eq!(options.after_context, Some(1));
+        assert_eq!(options.before_context, None);
+        assert_eq!(options.context, 3);
+    }
+
+    #[test]
+    fn parse_optional_argument() {
+        assert_eq!(test_options(&["--color", "a"]).color, ColorChoice::Auto);
+        assert_eq!(
+            test_options(&["--colour=always", "a"]).color,
+            ColorChoice::Always
+        );
+        assert_eq!(test_options(&["--colo=no", "a"]).color, ColorChoice::Never);
+        let options = test_options(&["--color", "never", "a"]);
+        assert_eq!(options.color, ColorChoice::Auto);
+        assert_eq!(options.patterns, vec!["never"]);
+        assert_eq!(
+            parse_args(&["--color=sometimes", "a"]),
+            Err(UsageError::InvalidArgument {
+                option: "color",
+                value: "sometimes".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(
+            parse_args(&["-Ez", "a"]),
+            Err(UsageError::UnknownOption('z'))
+        );
+        assert_eq!(
+            parse_args(&["--nope", "a"]),
+            Err(UsageError::UnknownLongOption("--nope".to_string()))
+        );
+        assert_eq!(
+            parse_args(&["-E", "-e"]),
+            Err(UsageError::MissingArgument('e'))
+        );
+        assert_eq!(
+            parse_args(&["--recursive=yes", "a"]),
+            Err(UsageError::UnexpectedArgument("recursive"))
+        );
+        assert_eq!(
+            parse_args(&["--debug=dfa", "a"]),
+            Err(UsageError::InvalidArgument {
+                option: "debug",
+                value: "dfa".to_string()
+            })
+        );
+        assert_eq!(
+            parse_args(&["-A", "two", "a"]),
+            Err(UsageError::InvalidContextLength("two".to_string()))
+        );
+        assert_eq!(
+            parse_args(&["-m", "-1", "a"]),
+            Err(UsageError::InvalidMaxCount)
+        );
+        assert_eq!(parse_args(&["-r"]), Err(UsageError::MissingPattern));
+    }
+}
@@ -0,0 +1,133 @@
+use std::io;
+use std::io::Write;
+
+// The SGR sequences used to highlight each part of the output, configured the way GNU grep
+// reads `GREP_COLORS`: a colon-separated list of `name=sgr` capabilities and boolean flags,
+// e.g. `ms=01;31:fn=35:ne`. An empty sequence leaves that part uncolored.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Colors {
+    // Matched text in selected lines.
+    pub selected_match: String,
+    // The rest of a selected line.
+    pub selected_line: String,
+    // Matched text in context lines, which only has matches with `-v`.
+    pub context_match: String,
+    pub context_line: String,
+    pub file_name: String,
+    pub line_number: String,
+    pub byte_offset: String,
+    pub separator: String,
+    // Follow each sequence with "erase to end of line", so a background color doesn't
+    // spill over to the end of the terminal line. Turned off by `ne`.
+    pub erase_line: bool,
+}
+
+impl Default for Colors {
+    // The defaults GNU grep uses when `GREP_COLORS` doesn't say otherwise.
+    fn default() -> Colors {
+        Colors {
+            selected_match: "01;31".to_string(),
+            selected_line: String::new(),
+            context_match: "01;31".to_string(),
+            context_line: String::new(),
+            file_name: "35".to_string(),
+            line_number: "32".to_string(),
+            byte_offset: "32".to_string(),
+            separator: "36".to_string(),
+            erase_line: true,
+        }
+    }
+}
+
+impl Colors {
+    // Colors that write every part as is.
+    pub fn plain() -> Colors {
+        Colors {
+            selected_match: String::new(),
+            selected_line: String::new(),
+            context_match: String::new(),
+            context_line: String::new(),
+            file_name: String::new(),
+            line_number: String::new(),
+            byte_offset: String::new(),
+            separator: String::new(),
+            erase_line: false,
+        }
+    }
+
+    // The defaults with the capabilities in `spec` applied on top. Like GNU grep, unknown
+    // capabilities and malformed sequences are ignored.
+    pub fn parse(spec: &str) -> Colors {
+        let mut colors = Colors::default();
+        for capability in spec.split(':') {
+            let (name, value) = match capability.split_once('=') {
+                Some((name, value)) => (name, value),
+                None => {
+                    if capability == "ne" {
+                        colors.erase_line = false;
+                    }
+                    continue;
+                }
+            };
+            if !value.chars().all(|c| c.is_ascii_digit() || c == ';') {
+                continue;
+            }
+            if name == "mt" {
+                colors.selected_match = value.to_string();
+                colors.context_match = value.to_string();
+                continue;
+            }
+            let target = match name {
+                "ms" => &mut colors.selected_match,
+                "mc" => &mut colors.context_match,
+                "sl" => &mut colors.selected_line,
+                "cx" => &mut colors.context_line,
+                "fn" => &mut colors.file_name,
+                "ln" => &mut colors.line_number,
+                "bn" => &mut colors.byte_offset,
+                "se" => &mut colors.separator,
+                _ => continue,
+            };
+            *target = value.to_string();
+        }
+        colors
+    }
+
+    // Writes `text` wrapped in the SGR sequence `sgr`, or as is when `sgr` is empty.
+    pub fn paint<W: Write>(&self, out: &mut W, sgr: &str, text: &[u8]) -> io::Result<()> {
+        if sgr.is_empty() || text.is_empty() {
+            return out.write_all(text);
+        }
+        let erase = if self.erase_line { "\x1b[K" } else { "" };
+        write!(out, "\x1b[{}m{}", sgr, erase)?;
+        out.write_all(text)?;
+        write!(out, "\x1b[m{}", erase)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_grep_colors() {
+        let colors = Colors::parse("mt=01;32:fn=:ln=x:ne:rv:zz=1");
+        assert_eq!(colors.selected_match, "01;32");
+        assert_eq!(colors.context_match, "01;32");
+        assert_eq!(colors.file_name, "");
+        assert_eq!(colors.line_number, "32");
+        assert!(!colors.erase_line);
+        assert_eq!(Colors::parse(""), Colors::default());
+    }
+
+    #[test]
+    fn paint_wraps_text() {
+        let mut out = vec![];
+        Colors::default().paint(&mut out, "35", b"a.txt").unwrap();
+        assert_eq!(out, b"\x1b[35m\x1b[Ka.txt\x1b[m\x1b[K");
+
+        let mut out = vec![];
+        Colors::plain().paint(&mut out, "", b"a.txt").unwrap();
+        assert_eq!(out, b"a.txt");
+    }
+}
@@ -0,0 +1,258 @@
+use std::collections::VecDeque;
+
+use crate::{is_word_char, MatchKind, Matcher};
+
+// Searches for a single needle with Boyer-Moore-Horspool: the needle is compared against the
+// input from its last byte, and on a mismatch the window shifts by how far the input byte
+// under the needle's end is from the end of the needle, which is usually its full length.
+pub struct Horspool {
+    needle: Vec<u8>,
+    shift: [usize; 256],
+    // Only accept occurrences that aren't preceded or followed by a word character.
+    word: bool,
+}
+
+impl Horspool {
+    pub fn new(needle: &str, word: bool) -> Horspool {
+        let needle = needle.as_bytes().to_vec();
+        let mut shift = [needle.len(); 256];
+        for (index, &b) in needle
+            .iter()
+            .enumerate()
+            .take(needle.len().saturating_sub(1))
+        {
+            shift[b as usize] = needle.len() - 1 - index;
+        }
+        Horspool {
+            needle,
+            shift,
+            word,
+        }
+    }
+
+    // The start of the first occurrence at or after `from`.
+    fn find_from(&self, haystack: &[u8], from: usize) -> Option<usize> {
+        let len = self.needle.len();
+        if len == 0 {
+            return Some(from);
+        }
+        let mut position = from;
+        while position + len <= haystack.len() {
+            let last = haystack[position + len - 1];
+            if last == self.needle[len - 1] && haystack[position..position + len] == self.needle {
+                return Some(position);
+            }
+            position += self.shift[last as usize];
+        }
+        None
+    }
+}
+
+impl Matcher for Horspool {
+    // Occurrences rejected by `word` are skipped by searching again one character later.
+    fn find_at(&self, input: &str, at: usize, _earliest: bool) -> Option<(usize, usize)> {
+        let mut from = at;
+        loop {
+            let start = self.find_from(input.as_bytes(), from)?;
+            let end = start + self.needle.len();
+            if !self.word || word_bounded(input, start, end) {
+                return Some((start, end));
+            }
+            from = start + input[start..].chars().next()?.len_utf8();
+        }
+    }
+}
+
+// Searches for many needles at once with an Aho-Corasick automaton: a trie of the needles
+// where every node also links to the longest proper suffix of its path that is a path in the
+// trie, so the input is scanned once however many needles there are. Every occurrence is
+// seen, including overlapping ones, and the leftmost is reported.
+pub struct AhoCorasick {
+    nodes: Vec<Node>,
+    // The length of each needle, by its index.
+    lengths: Vec<usize>,
+    max_length: usize,
+    match_kind: MatchKind,
+    word: bool,
+}
+
+#[derive(Default)]
+struct Node {
+    children: Vec<(u8, usize)>,
+    // Where to continue when no child matches the next byte.
+    fail: usize,
+    // The needles ending here, including those ending at nodes down the fail links.
+    outputs: Vec<usize>,
+}
+
+impl AhoCorasick {
+    pub fn new(needles: &[&str], match_kind: MatchKind, word: bool) -> AhoCorasick {
+        let mut nodes = vec![Node::default()];
+        for (index, needle) in needles.iter().enumerate() {
+            let mut current = 0;
+            for &b in needle.as_bytes() {
+                current = match nodes[current].child(b) {
+                    Some(child) => child,
+                    None => {
+                        nodes.push(Node::default());
+                        let child = nodes.len() - 1;
+                        nodes[current].children.push((b, child));
+                        child
+                    }
+                };
+            }
+            nodes[current].outputs.push(index);
+        }
+
+        // Breadth first, so the fail link of a node's parent is final before the node's own.
+        let mut queue: VecDeque<usize> = nodes[0].children.iter().map(|&(_, c)| c).collect();
+        while let Some(parent) = queue.pop_front() {
+            for (b, child) in nodes[parent].children.clone() {
+                let mut fail = nodes[parent].fail;
+                let fail = loop {
+                    if let Some(next) = nodes[fail].child(b) {
+                        break next;
+                    }
+                    if fail == 0 {
+                        break 0;
+                    }
+                    fail = nodes[fail].fail;
+                };
+                nodes[child].fail = fail;
+                let inherited = nodes[fail].outputs.clone();
+                nodes[child].outputs.extend(inherited);
+                queue.push_back(child);
+            }
+        }
+
+        let lengths: Vec<usize> = needles.iter().map(|needle| needle.len()).collect();
+        AhoCorasick {
+            nodes,
+            max_length: lengths.iter().copied().max().unwrap_or(0),
+            lengths,
+            match_kind,
+            word,
+        }
+    }
+
+    fn next(&self, mut current: usize, b: u8) -> usize {
+        loop {
+            if let Some(child) = self.nodes[current].child(b) {
+                return child;
+            }
+            if current == 0 {
+                return 0;
+            }
+            current = self.nodes[current].fail;
+        }
+    }
+}
+
+impl Node {
+    fn child(&self, b: u8) -> Option<usize> {
+        self.children
+            .iter()
+            .find(|&&(byte, _)| byte == b)
+            .map(|&(_, child)| child)
+    }
+}
+
+impl Matcher for AhoCorasick {
+    // Among occurrences starting at the same position, leftmost-first prefers the needle given
+    // first, as an alternation of the needles would.
+    fn find_at(&self, input: &str, at: usize, earliest: bool) -> Option<(usize, usize)> {
+        let bytes = input.as_bytes();
+        let mut best: Option<(usize, usize, usize)> = None;
+        let mut current = 0;
+        let mut position = at;
+        loop {
+            for &index in self.nodes[current].outputs.iter() {
+                let (start, end) = (position - self.lengths[index], position);
+                if self.word && !word_bounded(input, start, end) {
+                    continue;
+                }
+                if earliest {
+                    return Some((start, end));
+                }
+                let better = match best {
+                    None => true,
+                    Some((best_start, best_end, best_index)) => {
+                        start < best_start
+                            || (start == best_start
+                                && match self.match_kind {
+                                    MatchKind::LeftmostFirst => index < best_index,
+                                    MatchKind::LeftmostLongest => end > best_end,
+                                })
+                    }
+                };
+                if better {
+                    best = Some((start, end, index));
+                }
+            }
+            // Occurrences ending later can't start at or before the best one.
+            if let Some((best_start, _, _)) = best {
+                if position >= best_start + self.max_length {
+                    break;
+                }
+            }
+            if position == bytes.len() {
+                break;
+            }
+            current = self.next(current, bytes[position]);
+            position += 1;
+        }
+        best.map(|(start, end, _)| (start, end))
+    }
+}
+
+fn word_bounded(input: &str, start: usize, end: usize) -> bool {
+    !input[..start].chars().next_back().is_some_and(is_word_char)
+        && !input[end..].chars().next().is_some_and(is_word_char)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn horspool_finds_occurrences() {
+        let needle = Horspool::new("abab", false);
+        assert_eq!(needle.find_at("xxababab", 0, false), Some((2, 6)));
+        assert_eq!(needle.find_at("xxababab", 3, false), Some((4, 8)));
+        assert_eq!(needle.find_at("abaabab", 0, false), Some((3, 7)));
+        assert_eq!(needle.find_at("aba", 0, false), None);
+        assert_eq!(
+            Horspool::new("", false).find_at("ab", 1, false),
+            Some((1, 1))
+        );
+        assert_eq!(
+            Horspool::new("é", false).find_at("aéb", 0, false),
+            Some((1, 3))
+        );
+
+        let word = Horspool::new("foo", true);
+        assert_eq!(word.find_at("foobar xfoo foo", 0, false), Some((12, 15)));
+        assert_eq!(word.find_at("foobar", 0, false), None);
+    }
+
+    #[test]
+    fn aho_corasick_finds_leftmost() {
+        let first = AhoCorasick::new(&["cd", "abc", "b", "ab"], MatchKind::LeftmostFirst, false);
+        assert_eq!(first.find_at("xabcd", 0, false), Some((1, 4)));
+        assert_eq!(first.find_at("xabcd", 2, false), Some((2, 3)));
+        assert_eq!(first.find_at("xxcd", 0, false), Some((2, 4)));
+        assert_eq!(first.find_at("xyz", 0, false), None);
+
+        let longest = AhoCorasick::new(&["a", "ab", "abc"], MatchKind::LeftmostLongest, false);
+        assert_eq!(longest.find_at("zabcd", 0, false), Some((1, 4)));
+        let first = AhoCorasick::new(&["a", "ab", "abc"], MatchKind::LeftmostFirst, false);
+        assert_eq!(first.find_at("zabcd", 0, false), Some((1, 2)));
+
+        let word = AhoCorasick::new(&["foo", "bar"], MatchKind::LeftmostFirst, true);
+        assert_eq!(word.find_at("foobar bars bar", 0, false), Some((12, 15)));
+
+        let empty = AhoCorasick::new(&["x", ""], MatchKind::LeftmostFirst, false);
+        assert_eq!(empty.find_at("ab", 0, false), Some((0, 0)));
+        assert_eq!(empty.find_at("xb", 0, false), Some((0, 1)));
+    }
+}
@@ -1,216 +1,858 @@
-use std::collections::HashMap;
+mod backtrack;
+mod bre;
+mod class;
+mod cli;
+mod color;
+mod literal;
+mod search;
+mod walk;
+
+use std::collections::{HashMap, HashSet};
 use std::env;
 use std::io;
-use std::process;
+use std::io::{IsTerminal, Write};
+use std::path::Path;
 use std::process::ExitCode;
 use std::vec;
 
-// Usage: echo <input_text> | your_grep.sh -E <pattern>
+use thiserror::Error;
 
-const SPECIAL_MARKER: char = '\u{0000}';
+use backtrack::Backtracker;
+use bre::BreParser;
+use class::{case_variants, CharClass};
+use cli::{ColorChoice, Command, Options, Syntax, UsageError};
+use color::Colors;
+use literal::{AhoCorasick, Horspool};
+use search::Searcher;
+use walk::Walk;
 
-fn main() -> ExitCode {
-    if env::args().nth(1).unwrap() != "-E" {
-        println!("Expected first argument to be '-E'");
-        process::exit(1);
-    }
+// Usage: echo <input_text> | your_grep.sh [OPTION]... -E <pattern> [file...]
+
+const STDIN_NAME: &str = "(standard input)";
 
-    let pattern = env::args().nth(2).unwrap();
-    let mut input_line = String::new();
+fn main() -> ExitCode {
+    let options = match cli::parse(env::args().skip(1)) {
+        Ok(Command::Search(options)) => options,
+        Ok(Command::Help) => {
+            print!("{}", cli::HELP);
+            return ExitCode::from(0);
+        }
+        Err(err) => {
+            eprintln!("grep: {}", err);
+            if !matches!(err, UsageError::PatternFile { .. }) {
+                eprintln!("{}", cli::USAGE);
+                eprintln!("Try 'grep --help' for more information.");
+            }
+            return ExitCode::from(2);
+        }
+    };
+    let grep = match Grep::new(&options) {
+        Ok(grep) => grep,
+        Err(err) => {
+            eprintln!("grep: {}", err);
+            eprintln!("{}", err.caret_diagnostic());
+            return ExitCode::from(2);
+        }
+    };
 
-    io::stdin().read_line(&mut input_line).unwrap();
+    // Recursing without operands searches the working directory, reporting paths relative to it.
+    let mut paths = options.paths.clone();
+    let implicit_root = paths.is_empty() && options.recursive;
+    if paths.is_empty() {
+        paths.push(if implicit_root { "." } else { "-" }.to_string());
+    }
+    let with_prefix = paths.len() > 1 || (options.recursive && Path::new(&paths[0]).is_dir());
 
-    let grep = Grep {
-        pattern,
-        input: input_line,
+    let stdout = io::stdout();
+    let color = match options.color {
+        ColorChoice::Always => true,
+        ColorChoice::Auto => {
+            stdout.is_terminal() && env::var("TERM").is_ok_and(|term| term != "dumb")
+        }
+        ColorChoice::Never => false,
+    };
+    let colors = match (color, env::var("GREP_COLORS")) {
+        (true, Ok(spec)) => Colors::parse(&spec),
+        (true, Err(_)) => Colors::default(),
+        (false, _) => Colors::plain(),
     };
-    if grep.is_match() {
-        print!("match");
+    let out = io::BufWriter::new(stdout.lock());
+    let mut searcher = Searcher::new(&grep, &options, colors, out);
+    let mut matched = false;
+    let mut had_error = false;
+    for path in paths.iter() {
+        let files: Box<dyn Iterator<Item = walk::Entry>> = if options.recursive && path != "-" {
+            Box::new(Walk::new(Path::new(path), options.follow_links))
+        } else {
+            Box::new(std::iter::once(walk::Entry::File(path.into())))
+        };
+        for entry in files {
+            match entry {
+                walk::Entry::File(file) => {
+                    let file = if implicit_root {
+                        file.strip_prefix(".")
+                            .map(Path::to_path_buf)
+                            .unwrap_or(file)
+                    } else {
+                        file
+                    };
+                    let name = if file == Path::new("-") {
+                        STDIN_NAME.to_string()
+                    } else {
+                        file.display().to_string()
+                    };
+                    let prefix = if with_prefix {
+                        Some(name.as_str())
+                    } else {
+                        None
+                    };
+                    match searcher.search_path(&file, &name, prefix) {
+                        // With -q the exit status is known at the first selected line.
+                        Ok(selected) if selected > 0 && options.quiet => {
+                            searcher.into_inner().flush().unwrap();
+                            return ExitCode::from(0);
+                        }
+                        Ok(selected) => matched |= selected > 0,
+                        Err(err) => {
+                            eprintln!("grep: {}: {}", name, describe_error(&err));
+                            had_error = true;
+                        }
+                    }
+                }
+                walk::Entry::Error(file, err) => {
+                    eprintln!("grep: {}: {}", file.display(), describe_error(&err));
+                    had_error = true;
+                }
+                walk::Entry::Loop(file) => {
+                    eprintln!(
+                        "grep: {}: warning: recursive directory loop",
+                        file.display()
+                    );
+                }
+            }
+        }
+    }
+    searcher.into_inner().flush().unwrap();
+
+    if had_error {
+        ExitCode::from(2)
+    } else if matched {
         ExitCode::from(0)
     } else {
         ExitCode::from(1)
     }
 }
 
+// io::Error's Display appends " (os error N)", which grep doesn't print.
+fn describe_error(err: &io::Error) -> String {
+    let message = err.to_string();
+    match message.find(" (os error") {
+        Some(index) => message[..index].to_string(),
+        None => message,
+    }
+}
+
+// Decides whether lines match with whichever `Matcher` suits the patterns.
 struct Grep {
-    pattern: String,
-    input: String,
+    matcher: Box<dyn Matcher>,
+    // The name of each capture group, by number. Group 0 is the whole match.
+    group_names: Vec<Option<String>>,
+}
+
+// Finds matches in a line, given as byte offsets of their start and end.
+trait Matcher {
+    // The leftmost match starting at or after byte offset `at`, or the first match found at
+    // all when `earliest` is set and only whether there is one matters. Anchors and word
+    // boundaries still look at the whole input.
+    fn find_at(&self, input: &str, at: usize, earliest: bool) -> Option<(usize, usize)>;
+
+    // The same leftmost match as `find_at`, as slots holding the byte offsets of where the
+    // match and each of its capture groups start and end: slots 0 and 1 for the whole match,
+    // 2 and 3 for group 1 and so on. Matchers for patterns without groups only fill in the
+    // first two.
+    fn captures_at(&self, input: &str, at: usize) -> Option<Vec<Option<usize>>> {
+        let (start, end) = self.find_at(input, at, false)?;
+        Some(vec![Some(start), Some(end)])
+    }
+
+    // Whether a search since the last call gave up and reported no match, though there may
+    // have been one. Only `Backtracker` ever gives up, on lines that would take it too long.
+    fn gave_up(&self) -> bool {
+        false
+    }
+}
+
+// Which of the matches starting at the leftmost position is reported.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+enum MatchKind {
+    // The one a backtracking matcher finds first: alternatives are tried from left to right
+    // and repetitions are greedy, as in Perl.
+    #[default]
+    LeftmostFirst,
+    // The longest one, as POSIX specifies.
+    LeftmostLongest,
 }
 
 impl Grep {
-    fn is_match(&self) -> bool {
-        let chars: Vec<char> = self.pattern.chars().collect();
-        let pattern_parser = PatternParser::new(&chars);
-        let pattern = pattern_parser.parse();
-        println!("pattern: {:?}", pattern);
-
-        let modified_input = format!("{}{}{}", SPECIAL_MARKER, self.input, SPECIAL_MARKER);
-        let modified_pattern = Pattern::Sequence(vec![
-            Pattern::KleeneStar(Box::new(Pattern::AnyChar)),
-            pattern,
-            Pattern::KleeneStar(Box::new(Pattern::AnyChar)),
-        ]);
-        println!("modified_pattern: {:?}", modified_pattern);
+    // Every pattern is parsed on its own and the results are or-ed together into a single
+    // automaton, so a line is scanned once and matches if any of the patterns matches it. As
+    // in GNU grep, a pattern containing newlines is a list of patterns.
+    fn new(options: &Options) -> Result<Grep, PatternError> {
+        let debug = &options.debug;
+        let patterns = options
+            .patterns
+            .iter()
+            .flat_map(|patterns| patterns.split('\n'));
+        let match_kind = if options.posix {
+            MatchKind::LeftmostLongest
+        } else {
+            MatchKind::LeftmostFirst
+        };
+        // A whole line is a whole word too.
+        let word = options.word_regexp && !options.line_regexp;
+
+        // Plain literals don't need an automaton. Case folding and whole lines are left to the
+        // nfa, with each literal turned into a sequence of characters.
+        let fixed = options.syntax == Syntax::Fixed;
+        if fixed && !options.ignore_case && !options.line_regexp {
+            let needles: Vec<&str> = patterns.collect();
+            let matcher: Box<dyn Matcher> = if needles.len() == 1 {
+                Box::new(Horspool::new(needles[0], word))
+            } else {
+                Box::new(AhoCorasick::new(&needles, match_kind, word))
+            };
+            return Ok(Grep {
+                matcher,
+                group_names: vec![None],
+            });
+        }
+
+        let mut parsed = vec![];
+        let mut group_names = vec![None];
+        let mut backrefs = false;
+        for pattern in patterns {
+            let chars: Vec<char> = pattern.chars().collect();
+            let result = match options.syntax {
+                Syntax::Extended => PatternParser::new(&chars, options.ignore_case).parse(),
+                Syntax::Basic => BreParser::new(&chars, options.ignore_case).parse(),
+                Syntax::Fixed => Ok(Pattern::Sequence(
+                    chars.into_iter().map(Pattern::Literal).collect(),
+                )),
+            };
+            let mut pattern = result.map_err(|error| PatternError {
+                pattern: pattern.to_string(),
+                error,
+            })?;
+            // Groups are numbered within each pattern, and then across the patterns in order.
+            let offset = group_names.len() - 1;
+            pattern.visit(&mut |pattern| match pattern {
+                Pattern::Group(_, index, name) => {
+                    *index += offset;
+                    group_names.push(name.clone());
+                }
+                Pattern::Backref(index) => {
+                    *index += offset;
+                    backrefs = true;
+                }
+                _ => {}
+            });
+            parsed.push(pattern);
+        }
+        let pattern = if parsed.len() == 1 {
+            parsed.pop().unwrap()
+        } else {
+            Pattern::Or(parsed)
+        };
+        let pattern = if options.ignore_case {
+            Pattern::IgnoreCase(Box::new(pattern))
+        } else {
+            pattern
+        };
+        let pattern = if options.line_regexp {
+            Pattern::Sequence(vec![Pattern::Start, pattern, Pattern::End])
+        } else {
+            pattern
+        };
+        if debug.ast {
+            eprintln!("pattern: {:?}", pattern);
+        }
+
+        // The automaton is much faster, but only the backtracker can follow backreferences.
+        // The automaton lets any text stand in for them, so it still rules out the lines where
+        // nothing matches and finds where the backtracker can start.
+        let backtracked = backrefs.then(|| pattern.clone());
         let mut nfa_builder = NfaBuilder::new();
-        let nfa = nfa_builder.of(modified_pattern);
-        println!("nfa: {:?}", nfa);
-        let nfa_runner = NfaRunner::new(nfa);
-        nfa_runner.run(&modified_input)
+        let nfa = nfa_builder.of(pattern);
+        let nfa = nfa_builder.accepting(nfa);
+        if debug.nfa {
+            eprintln!("nfa: {:?}", nfa);
+        }
+        let runner = NfaRunner::new(nfa, match_kind, word, debug.trace);
+        let matcher: Box<dyn Matcher> = match backtracked {
+            Some(pattern) => Box::new(Backtracker::new(pattern, runner, match_kind, word)),
+            None => Box::new(runner),
+        };
+        Ok(Grep {
+            matcher,
+            group_names,
+        })
+    }
+
+    fn is_match(&self, input: &str) -> bool {
+        self.matcher.find_at(input, 0, true).is_some()
+    }
+
+    // See `Matcher::gave_up`.
+    fn gave_up(&self) -> bool {
+        self.matcher.gave_up()
+    }
+
+    // Byte offsets of the start and end of the leftmost match in `input`.
+    fn find(&self, input: &str) -> Option<(usize, usize)> {
+        self.matcher.find_at(input, 0, false)
+    }
+
+    // Every non-overlapping match in `input`, from left to right.
+    fn find_iter<'s>(&self, input: &'s str) -> Matches<'_, 's> {
+        Matches {
+            matcher: self.matcher.as_ref(),
+            input,
+            at: Some(0),
+            last_end: None,
+        }
+    }
+
+    // The spans of the leftmost match in `input` and of each capture group, by group number.
+    // Groups that took no part in the match have no span.
+    fn captures(&self, input: &str) -> Option<Vec<Option<(usize, usize)>>> {
+        let mut slots = self.matcher.captures_at(input, 0)?;
+        slots.resize(2 * self.group_names.len(), None);
+        let spans = slots
+            .chunks(2)
+            .map(|pair| match pair {
+                [Some(start), Some(end)] => Some((*start, *end)),
+                _ => None,
+            })
+            .collect();
+        Some(spans)
+    }
+
+    // The groups of the leftmost match, as `--debug=groups` prints them: each group's name or
+    // number and its span, or `-` when it took no part.
+    fn describe_groups(&self, input: &str) -> String {
+        let spans = match self.captures(input) {
+            Some(spans) => spans,
+            None => return "no match".to_string(),
+        };
+        let mut description = vec![];
+        for (index, span) in spans.iter().enumerate() {
+            let name = match &self.group_names[index] {
+                Some(name) => name.clone(),
+                None => index.to_string(),
+            };
+            match span {
+                Some((start, end)) => description.push(format!("{}={}..{}", name, start, end)),
+                None => description.push(format!("{}=-", name)),
+            }
+        }
+        description.join(" ")
     }
 }
 
-#[derive(Debug)]
+// Word characters are the ones `-w` doesn't allow right before or after a match.
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+#[derive(Debug, Clone)]
 enum Pattern {
     Start,
     End,
     Literal(char),
     AnyDigit,
     AnyChar,
-    AnyCharIn(Vec<char>),
-    AnyCharNotIn(Vec<char>),
+    AnyCharIn(CharClass),
+    AnyCharNotIn(CharClass),
     OneOrMore(Box<Pattern>),
     KleeneStar(Box<Pattern>),
+    // The pattern repeated at least `min` and at most `max` times, or without an upper
+    // bound when `max` is None.
+    Repeat(Box<Pattern>, usize, Option<usize>),
     Sequence(Vec<Pattern>),
-    Or(Box<Pattern>, Box<Pattern>),
+    Or(Vec<Pattern>),
+    // Literals and classes inside match regardless of case.
+    IgnoreCase(Box<Pattern>),
+    // A capture group with its number, counted by opening parenthesis from 1, and its name
+    // if it has one.
+    Group(Box<Pattern>, usize, Option<String>),
+    // The text last matched by the group with this number.
+    Backref(usize),
+}
+
+impl Pattern {
+    // Number of atoms the nfa built from this pattern has, with counted repetitions expanded.
+    fn size(&self) -> usize {
+        match self {
+            Pattern::Repeat(pattern, min, max) => {
+                pattern.size().saturating_mul(max.unwrap_or(min + 1))
+            }
+            Pattern::OneOrMore(pattern)
+            | Pattern::KleeneStar(pattern)
+            | Pattern::IgnoreCase(pattern)
+            | Pattern::Group(pattern, _, _) => pattern.size(),
+            Pattern::Sequence(patterns) | Pattern::Or(patterns) => patterns
+                .iter()
+                .fold(0, |size, pattern| size.saturating_add(pattern.size())),
+            _ => 1,
+        }
+    }
+
+    // Calls `visit` with this pattern and then every pattern inside it, from left to right.
+    fn visit(&mut self, visit: &mut dyn FnMut(&mut Pattern)) {
+        visit(self);
+        match self {
+            Pattern::OneOrMore(pattern)
+            | Pattern::KleeneStar(pattern)
+            | Pattern::Repeat(pattern, _, _)
+            | Pattern::IgnoreCase(pattern)
+            | Pattern::Group(pattern, _, _) => pattern.visit(visit),
+            Pattern::Sequence(patterns) | Pattern::Or(patterns) => {
+                for pattern in patterns.iter_mut() {
+                    pattern.visit(visit);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[derive(Debug, Error, PartialEq)]
+enum ParseError {
+    #[error("trailing backslash")]
+    TrailingBackslash(usize),
+    #[error("unmatched [ or [^")]
+    UnterminatedClass(usize),
+    #[error("unmatched ( or missing )")]
+    UnmatchedParen(usize),
+    #[error("nothing to repeat before '{1}'")]
+    NothingToRepeat(usize, char),
+    #[error("invalid content of {{}}")]
+    InvalidInterval(usize),
+    #[error(
+        "repetition count is larger than {} or the repeated pattern is too large",
+        REPEAT_LIMIT
+    )]
+    RepetitionTooLarge(usize),
+    #[error("invalid character class name")]
+    InvalidClassName(usize),
+    #[error("invalid range end")]
+    InvalidRange(usize),
+    #[error("invalid group name")]
+    InvalidGroupName(usize),
+    #[error("invalid back reference")]
+    InvalidBackReference(usize),
+}
+
+impl ParseError {
+    // Offset, in characters, of the position in the pattern the error points at.
+    fn offset(&self) -> usize {
+        match self {
+            ParseError::TrailingBackslash(offset)
+            | ParseError::UnterminatedClass(offset)
+            | ParseError::UnmatchedParen(offset)
+            | ParseError::NothingToRepeat(offset, _)
+            | ParseError::InvalidInterval(offset)
+            | ParseError::RepetitionTooLarge(offset)
+            | ParseError::InvalidClassName(offset)
+            | ParseError::InvalidRange(offset)
+            | ParseError::InvalidGroupName(offset)
+            | ParseError::InvalidBackReference(offset) => *offset,
+        }
+    }
+}
+
+// A `ParseError` together with the pattern it was found in.
+#[derive(Debug, Error)]
+#[error("{error}")]
+struct PatternError {
+    pattern: String,
+    error: ParseError,
+}
+
+impl PatternError {
+    // Renders the pattern with a caret under the offending character.
+    fn caret_diagnostic(&self) -> String {
+        format!("{}\n{}^", self.pattern, " ".repeat(self.error.offset()))
+    }
+}
+
+// Counted repetition copies the repeated sub-NFA once per count, so the counts are capped, and
+// so is the size of the pattern they expand to, since nested counts multiply: `a{1000}` is
+// fine but `(a{1000}){1000}` would build a million states.
+const REPEAT_LIMIT: usize = 1000;
+const EXPANSION_LIMIT: usize = 100_000;
+
+// `pattern` repeated `min` to `max` times by the interval at `open`, unless the expanded
+// pattern would grow past `EXPANSION_LIMIT`.
+fn repeat(
+    pattern: Pattern,
+    min: usize,
+    max: Option<usize>,
+    open: usize,
+) -> Result<Pattern, ParseError> {
+    let pattern = Pattern::Repeat(Box::new(pattern), min, max);
+    if pattern.size() > EXPANSION_LIMIT {
+        return Err(ParseError::RepetitionTooLarge(open));
+    }
+    Ok(pattern)
 }
 
+// Recursive-descent parser for extended regular expressions. From loosest to tightest
+// binding the grammar is:
+//
+//   alternation   := concatenation ('|' concatenation)*
+//   concatenation := ('(?i)' | repetition)*
+//   repetition    := atom ('*' | '+' | '?' | '{' interval '}')*
+//   atom          := literal | '.' | '^' | '$' | escape | class | '(' group alternation ')'
+//   group         := ('?:' | '?<' name '>' | '?P<' name '>')?
+//
+// Among the escapes, `\1` to `\9` refer back to the text matched by a group opened before.
+// A `*`, `+` or `?` with nothing before it to repeat is a literal, as in basic ones.
 struct PatternParser<'a> {
     input: &'a [char],
     index: usize,
-    patterns: Vec<Pattern>,
+    // Number of groups we are currently inside of; a `)` outside of any group is a literal.
+    depth: usize,
+    // Number of capture groups opened so far.
+    groups: usize,
+    // Set by `(?i)` for the rest of the enclosing group, including later alternatives.
+    ignore_case: bool,
+    // Set by `-i`, which has the nfa fold the case of the whole pattern.
+    fold_case: bool,
 }
 
 impl<'a> PatternParser<'a> {
-    fn new(input: &'a [char]) -> PatternParser {
+    fn new(input: &'a [char], fold_case: bool) -> PatternParser<'a> {
         PatternParser {
             input,
             index: 0,
-            patterns: Vec::new(),
+            depth: 0,
+            groups: 0,
+            ignore_case: false,
+            fold_case,
         }
     }
 
-    fn parse(self) -> Pattern {
+    fn parse(self) -> Result<Pattern, ParseError> {
         let mut parser: PatternParser<'_> = self;
-        parser.internal_parse()
+        parser.alternation()
     }
 
-    fn internal_parse(&mut self) -> Pattern {
-        while let Some(next) = self.next_pattern() {
-            self.patterns.push(next);
+    fn peek(&self) -> Option<char> {
+        self.input.get(self.index).copied()
+    }
+
+    fn alternation(&mut self) -> Result<Pattern, ParseError> {
+        let mut branches = vec![self.concatenation()?];
+        // As in GNU grep, an empty branch matches the empty string.
+        while self.peek() == Some('|') {
+            self.index += 1;
+            branches.push(self.concatenation()?);
         }
-        if self.patterns.len() == 0 {
-            return Pattern::AnyChar;
-        } else if self.patterns.len() == 1 {
-            self.patterns.pop().unwrap()
+        let mut branches: Vec<Pattern> = branches.into_iter().map(sequence_of).collect();
+        if branches.len() == 1 {
+            Ok(branches.pop().unwrap())
         } else {
-            Pattern::Sequence(self.patterns.drain(..).collect())
+            Ok(Pattern::Or(branches))
+        }
+    }
+
+    fn concatenation(&mut self) -> Result<Vec<Pattern>, ParseError> {
+        let mut patterns = vec![];
+        while let Some(c) = self.peek() {
+            if c == '|' || (c == ')' && self.depth > 0) {
+                break;
+            }
+            if self.input[self.index..].starts_with(&['(', '?', 'i', ')']) {
+                self.index += 4;
+                self.ignore_case = true;
+                continue;
+            }
+            patterns.push(self.repetition()?);
         }
+        Ok(patterns)
     }
 
-    fn next_pattern(&mut self) -> Option<Pattern> {
-        if self.index >= self.input.len() {
-            return None;
+    fn repetition(&mut self) -> Result<Pattern, ParseError> {
+        let mut pattern = self.atom()?;
+        while let Some(c) = self.peek() {
+            let start = self.index;
+            pattern = match c {
+                '*' => Pattern::KleeneStar(Box::new(pattern)),
+                '+' => Pattern::OneOrMore(Box::new(pattern)),
+                '?' => Pattern::Repeat(Box::new(pattern), 0, Some(1)),
+                '{' => match self.interval()? {
+                    Some((min, max)) => repeat(pattern, min, max, start)?,
+                    // Not an interval, so the brace is a literal for the next atom.
+                    None => break,
+                },
+                _ => break,
+            };
+            self.index += 1;
+        }
+        Ok(pattern)
+    }
+
+    // Parses `{n}`, `{n,}`, `{,m}` or `{n,m}` starting at the `{` and leaves the index on the
+    // closing `}`. A brace that isn't followed by a digit or a comma is not an interval.
+    fn interval(&mut self) -> Result<Option<(usize, Option<usize>)>, ParseError> {
+        let open = self.index;
+        let mut index = open + 1;
+        if !matches!(self.input.get(index), Some(c) if c.is_ascii_digit() || *c == ',') {
+            return Ok(None);
+        }
+        let min = self.count(&mut index, open)?;
+        let max = if self.input.get(index) == Some(&',') {
+            index += 1;
+            self.count(&mut index, open)?
+        } else {
+            Some(min.unwrap())
+        };
+        if self.input.get(index) != Some(&'}') {
+            return Err(ParseError::InvalidInterval(open));
         }
+        let min = min.unwrap_or(0);
+        if matches!(max, Some(max) if max < min) {
+            return Err(ParseError::InvalidInterval(open));
+        }
+        self.index = index;
+        Ok(Some((min, max)))
+    }
+
+    // Reads the decimal number at `index`, if there is one.
+    fn count(&self, index: &mut usize, open: usize) -> Result<Option<usize>, ParseError> {
+        let mut count = None;
+        while let Some(digit) = self.input.get(*index).and_then(|c| c.to_digit(10)) {
+            let value = count.unwrap_or(0) * 10 + digit as usize;
+            if value > REPEAT_LIMIT {
+                return Err(ParseError::RepetitionTooLarge(open));
+            }
+            count = Some(value);
+            *index += 1;
+        }
+        Ok(count)
+    }
+
+    fn atom(&mut self) -> Result<Pattern, ParseError> {
+        let start = self.index;
         let current = self.input[self.index];
-        let next = match current {
+        self.index += 1;
+        let atom = match current {
             '\\' => {
+                let c = self.peek().ok_or(ParseError::TrailingBackslash(start))?;
                 self.index += 1;
-                let c = self.input[self.index];
                 match c {
                     'd' => Pattern::AnyDigit,
-                    'w' => Pattern::AnyCharIn(
-                        "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789_"
-                            .chars()
-                            .collect(),
-                    ),
-                    's' => Pattern::AnyCharIn(" \t\r\n".chars().collect()),
-                    'D' => Pattern::AnyCharNotIn("0123456789".chars().collect()),
-                    'W' => Pattern::AnyCharNotIn(
-                        "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789_"
-                            .chars()
-                            .collect(),
-                    ),
-                    'S' => Pattern::AnyCharNotIn(" \t\r\n".chars().collect()),
+                    'w' => Pattern::AnyCharIn(CharClass::word()),
+                    's' => Pattern::AnyCharIn(CharClass::space()),
+                    'D' => Pattern::AnyCharNotIn(CharClass::digit()),
+                    'W' => Pattern::AnyCharNotIn(CharClass::word()),
+                    'S' => Pattern::AnyCharNotIn(CharClass::space()),
+                    '1'..='9' => self.backref(start, c)?,
                     _ => Pattern::Literal(c),
                 }
             }
             '.' => Pattern::AnyChar,
-            '[' => {
+            '[' => self.bracket_expression(start)?,
+            '(' => {
+                let ignore_case = self.ignore_case;
+                let capture = self.group()?;
+                let index = self.groups;
+                self.depth += 1;
+                let inner = self.alternation()?;
+                if self.peek() != Some(')') {
+                    return Err(ParseError::UnmatchedParen(start));
+                }
                 self.index += 1;
-                let mut chars = Vec::new();
-                let mut is_not = false;
-                if self.input[self.index] == '^' {
-                    is_not = true;
-                    self.index += 1;
+                self.depth -= 1;
+                self.ignore_case = ignore_case;
+                // The group's contents were already wrapped as needed.
+                return Ok(match capture {
+                    Some(name) => Pattern::Group(Box::new(inner), index, name),
+                    None => inner,
+                });
+            }
+            '^' => Pattern::Start,
+            '$' => Pattern::End,
+            _ => Pattern::Literal(current),
+        };
+        if self.ignore_case {
+            Ok(Pattern::IgnoreCase(Box::new(atom)))
+        } else {
+            Ok(atom)
+        }
+    }
+
+    // The backreference `\digit` at `start`, which has to refer to a group opened before it.
+    fn backref(&self, start: usize, digit: char) -> Result<Pattern, ParseError> {
+        let index = digit.to_digit(10).unwrap() as usize;
+        if index > self.groups {
+            return Err(ParseError::InvalidBackReference(start));
+        }
+        Ok(Pattern::Backref(index))
+    }
+
+    // Parses what follows the `(` of a group. Returns None for a non-capturing `(?:`, and
+    // otherwise counts the capture group and returns its name, if it has one.
+    fn group(&mut self) -> Result<Option<Option<String>>, ParseError> {
+        let rest = &self.input[self.index..];
+        if rest.starts_with(&['?', ':']) {
+            self.index += 2;
+            return Ok(None);
+        }
+        self.groups += 1;
+        let name_start = if rest.starts_with(&['?', '<']) {
+            self.index + 2
+        } else if rest.starts_with(&['?', 'P', '<']) {
+            self.index + 3
+        } else {
+            return Ok(Some(None));
+        };
+        let name_end = (name_start..self.input.len())
+            .find(|&i| self.input[i] == '>')
+            .ok_or(ParseError::InvalidGroupName(self.index - 1))?;
+        let name: String = self.input[name_start..name_end].iter().collect();
+        let valid = name.chars().all(is_word_char)
+            && name
+                .chars()
+                .next()
+                .filter(|c| !c.is_ascii_digit())
+                .is_some();
+        if !valid {
+            return Err(ParseError::InvalidGroupName(self.index - 1));
+        }
+        self.index = name_end + 1;
+        Ok(Some(Some(name)))
+    }
+
+    // Parses the members of a bracket expression that starts at `start`, up to and including the
+    // closing `]`. A `]` right after the opening `[` or `[^` is a member, `-` between two members
+    // is a range, `\d`, `\w`, `\s` and their negations add the same characters as outside of
+    // brackets, any other escaped character is a member, and `[:name:]` adds a POSIX class.
+    fn bracket_expression(&mut self, start: usize) -> Result<Pattern, ParseError> {
+        let mut is_not = false;
+        if self.peek() == Some('^') {
+            is_not = true;
+            self.index += 1;
+        }
+        let mut class = CharClass::new();
+        let mut first = true;
+        loop {
+            let member_start = self.index;
+            let c = self.peek().ok_or(ParseError::UnterminatedClass(start))?;
+            self.index += 1;
+            if c == ']' && !first {
+                break;
+            }
+            first = false;
+            match c {
+                '[' if self.peek() == Some(':') => {
+                    class.union(&self.posix_class(member_start)?);
                 }
-                loop {
-                    let c = self.input[self.index];
-                    if c == ']' {
-                        break;
-                    }
-                    chars.push(c);
+                '\\' => {
+                    let escaped = self.peek().ok_or(ParseError::UnterminatedClass(start))?;
                     self.index += 1;
+                    match escaped {
+                        'd' => class.union(&CharClass::digit()),
+                        'w' => class.union(&CharClass::word()),
+                        's' => class.union(&CharClass::space()),
+                        'D' => class.union(&self.negated(CharClass::digit())),
+                        'W' => class.union(&self.negated(CharClass::word())),
+                        'S' => class.union(&self.negated(CharClass::space())),
+                        _ => self.range_or_member(&mut class, escaped, member_start, start)?,
+                    }
                 }
-                if is_not {
-                    Pattern::AnyCharNotIn(chars)
-                } else {
-                    Pattern::AnyCharIn(chars)
-                }
+                _ => self.range_or_member(&mut class, c, member_start, start)?,
             }
-            '(' => {
+        }
+        if is_not {
+            Ok(Pattern::AnyCharNotIn(class))
+        } else {
+            Ok(Pattern::AnyCharIn(class))
+        }
+    }
+
+    // The characters not in `class`, for `\D`, `\W` and `\S` in a bracket expression. Ignoring
+    // case, `class` is folded before it is negated: folding `\W` afterwards, along with the rest
+    // of the bracket expression, would add the `s` that its `ſ` is a case variant of.
+    fn negated(&self, class: CharClass) -> CharClass {
+        if self.ignore_case || self.fold_case {
+            class.case_folded().negated()
+        } else {
+            class.negated()
+        }
+    }
+
+    // Adds `lo` to the class, or the range from `lo` when it is followed by `-` and another
+    // member. A `-` right before the closing `]` is a member rather than a range.
+    fn range_or_member(
+        &mut self,
+        class: &mut CharClass,
+        lo: char,
+        member_start: usize,
+        start: usize,
+    ) -> Result<(), ParseError> {
+        let is_range =
+            self.peek() == Some('-') && !matches!(self.input.get(self.index + 1), None | Some(']'));
+        if !is_range {
+            class.push(lo, lo);
+            return Ok(());
+        }
+        self.index += 1;
+        let hi = match self.input[self.index] {
+            '\\' => {
                 self.index += 1;
-                let next_close = self.next_index(b')' as char).expect("Expected ')'");
-                let index = self.index;
-                let pipe_index = self.next_index(b'|' as char);
-                self.index = next_close;
-                if let Some(pipe_index) = pipe_index {
-                    let left = PatternParser::new(&self.input[index..pipe_index]).internal_parse();
-                    let right = PatternParser::new(&self.input[pipe_index + 1..next_close])
-                        .internal_parse();
-                    Pattern::Or(Box::new(left), Box::new(right))
-                } else {
-                    PatternParser::new(&self.input[index..next_close]).internal_parse()
+                match self.input.get(self.index) {
+                    None => return Err(ParseError::UnterminatedClass(start)),
+                    // a class can't end a range, as it can't start one
+                    Some('d' | 'w' | 's' | 'D' | 'W' | 'S') => {
+                        return Err(ParseError::InvalidRange(member_start));
+                    }
+                    Some(&hi) => hi,
                 }
             }
-            '|' => {
-                let left = self.patterns.pop().expect("Expected left pattern before |");
-                self.index += 1;
-                let right = PatternParser::new(&self.input[self.index..])
-                    .next_pattern()
-                    .expect("Expected right pattern after |");
-                self.index -= 1;
-                Pattern::Or(Box::new(left), Box::new(right))
-            }
-            '*' => {
-                let left = self.patterns.pop().expect("Expected left pattern before *");
-                Pattern::KleeneStar(Box::new(left))
-            }
-            '+' => {
-                let left = self.patterns.pop().expect("Expected left pattern before +");
-                Pattern::OneOrMore(Box::new(left))
-            }
-            '?' => {
-                let left = self.patterns.pop().expect("Expected left pattern before ?");
-                Pattern::Or(
-                    Box::new(Pattern::Sequence(vec![])),
-                    Box::new(Pattern::OneOrMore(Box::new(left))),
-                )
+            '[' if self.input.get(self.index + 1) == Some(&':') => {
+                return Err(ParseError::InvalidRange(member_start));
             }
-            '^' => Pattern::Start,
-            '$' => Pattern::End,
-            _ => Pattern::Literal(current),
+            hi => hi,
         };
         self.index += 1;
-        Some(next)
+        if hi < lo {
+            return Err(ParseError::InvalidRange(member_start));
+        }
+        class.push(lo, hi);
+        Ok(())
     }
 
-    fn next_index(&self, c: char) -> Option<usize> {
-        for i in self.index..self.input.len() {
-            if self.input[i] == c {
-                return Some(i);
-            }
-        }
-        None
+    // Parses `[:name:]`, with the index just past the opening `[`.
+    fn posix_class(&mut self, start: usize) -> Result<CharClass, ParseError> {
+        let name_start = self.index + 1;
+        let name_end = (name_start..self.input.len())
+            .find(|&i| self.input[i] == ':' && self.input.get(i + 1) == Some(&']'))
+            .ok_or(ParseError::InvalidClassName(start))?;
+        let name: String = self.input[name_start..name_end].iter().collect();
+        self.index = name_end + 2;
+        CharClass::posix(&name).ok_or(ParseError::InvalidClassName(start))
+    }
+}
+
+// A single pattern stays as it is; anything else becomes a sequence, which for no patterns at
+// all matches the empty string.
+fn sequence_of(mut patterns: Vec<Pattern>) -> Pattern {
+    if patterns.len() == 1 {
+        patterns.pop().unwrap()
+    } else {
+        Pattern::Sequence(patterns)
     }
 }
 
@@ -222,6 +864,22 @@ struct Nfa {
     states: HashMap<StateId, NfaState>,
 }
 
+impl Nfa {
+    // Number of slots the `Save` transitions write to, and at least the two for the whole match.
+    fn slots(&self) -> usize {
+        self.states
+            .values()
+            .flat_map(|state| state.transition.iter())
+            .filter_map(|(input, _)| match input {
+                StateInput::Save(slot) => Some(slot + 1),
+                _ => None,
+            })
+            .max()
+            .unwrap_or(0)
+            .max(2)
+    }
+}
+
 impl std::fmt::Debug for Nfa {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let state_ids = self.states.keys().collect::<Vec<&StateId>>();
@@ -230,22 +888,15 @@ impl std::fmt::Debug for Nfa {
             let state = self.states.get(state_id).unwrap();
             for (input, next_state) in state.transition.iter() {
                 let input_str = match input {
-                    StateInput::Literal(c) => {
-                        if *c == SPECIAL_MARKER {
-                            "SPECIAL_MARKER".to_string()
-                        } else {
-                            format!("{}", c)
-                        }
-                    }
+                    StateInput::Literal(c) => format!("{}", c),
                     StateInput::AnyDigit => r"\d".to_string(),
                     StateInput::AnyChar => ".".to_string(),
-                    StateInput::AnyCharIn(chars) => {
-                        format!("[{}]", chars.iter().collect::<String>())
-                    }
-                    StateInput::AnyCharNotIn(chars) => {
-                        format!("[^{}]", chars.iter().collect::<String>())
-                    }
+                    StateInput::AnyCharIn(class) => format!("[{}]", class),
+                    StateInput::AnyCharNotIn(class) => format!("[^{}]", class),
                     StateInput::Epsilon => "ε".to_string(),
+                    StateInput::Start => "^".to_string(),
+                    StateInput::End => "$".to_string(),
+                    StateInput::Save(slot) => format!("save {}", slot),
                 };
                 state_str.push_str(&format!("{} -> {} -> {}", state_id, input_str, next_state));
                 state_str.push('\n');
@@ -265,23 +916,34 @@ struct NfaState {
     transition: Vec<(StateInput, usize)>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 enum StateInput {
     Literal(char),
     AnyDigit,
     AnyChar,
-    AnyCharIn(Vec<char>),
-    AnyCharNotIn(Vec<char>),
+    AnyCharIn(CharClass),
+    AnyCharNotIn(CharClass),
     Epsilon,
+    // Zero-width transitions that are only followed at the start or the end of the line.
+    Start,
+    End,
+    // Zero-width transition that records the current position in a slot, for the start or
+    // end of a capture group.
+    Save(usize),
 }
 
 struct NfaBuilder {
     id_: usize,
+    // Set while building the inside of a `Pattern::IgnoreCase`.
+    ignore_case: bool,
 }
 
 impl NfaBuilder {
     fn new() -> NfaBuilder {
-        NfaBuilder { id_: 0 }
+        NfaBuilder {
+            id_: 0,
+            ignore_case: false,
+        }
     }
 
     fn of(&mut self, pattern: Pattern) -> Nfa {
@@ -299,14 +961,48 @@ impl NfaBuilder {
                 let inner = self.of(*pattern);
                 self.kleene_star(inner)
             }
+            Pattern::Repeat(pattern, min, max) => {
+                let inner = self.of(*pattern);
+                self.repeat(inner, min, max)
+            }
             Pattern::Sequence(patterns) => self.sequence(patterns),
-            Pattern::Or(left, right) => {
-                let left = self.of(*left);
-                let right = self.of(*right);
-                self.or(left, right)
+            Pattern::IgnoreCase(pattern) => {
+                let ignore_case = self.ignore_case;
+                self.ignore_case = true;
+                let nfa = self.of(*pattern);
+                self.ignore_case = ignore_case;
+                nfa
+            }
+            Pattern::Or(patterns) => {
+                let nfas = patterns
+                    .into_iter()
+                    .map(|pattern| self.of(pattern))
+                    .collect();
+                self.or(nfas)
             }
-            Pattern::Start => self.literal(SPECIAL_MARKER),
-            Pattern::End => self.literal(SPECIAL_MARKER),
+            Pattern::Start => self.assertion(StateInput::Start),
+            Pattern::End => self.assertion(StateInput::End),
+            Pattern::Group(pattern, index, _) => {
+                let open = self.assertion(StateInput::Save(2 * index));
+                let inner = self.of(*pattern);
+                let close = self.assertion(StateInput::Save(2 * index + 1));
+                self.concat(vec![open, inner, close])
+            }
+            // No automaton can match what a group matched again, so any text stands in for it.
+            // The nfa then matches wherever the pattern does and maybe elsewhere too, which
+            // makes it a prefilter for `Backtracker`.
+            Pattern::Backref(_) => {
+                let any = self.any_char();
+                self.kleene_star(any)
+            }
+        }
+    }
+
+    fn fold_case(&self, chars: CharClass) -> CharClass {
+        if self.ignore_case {
+            chars.case_folded()
+        } else {
+            chars
         }
     }
 
@@ -316,6 +1012,10 @@ impl NfaBuilder {
     }
 
     fn literal(&mut self, c: char) -> Nfa {
+        // a literal with other cases becomes the class of all of them
+        if self.ignore_case && !case_variants(c).is_empty() {
+            return self.any_char_in(CharClass::from_ranges([(c, c)]));
+        }
         let end = NfaState {
             id: self.next_id(),
             transition: vec![],
@@ -332,6 +1032,42 @@ impl NfaBuilder {
         }
     }
 
+    // a single zero-width transition, for anchors and the boundaries of groups
+    fn assertion(&mut self, input: StateInput) -> Nfa {
+        let end = NfaState {
+            id: self.next_id(),
+            transition: vec![],
+        };
+        let start = NfaState {
+            id: self.next_id(),
+            transition: vec![(input, end.id)],
+        };
+        Nfa {
+            start: start.id,
+            end: vec![end.id],
+            states: [(start.id, start), (end.id, end)].into_iter().collect(),
+        }
+    }
+
+    // adds a single accepting state without transitions after the nfa. The end states of an
+    // nfa can have transitions of their own, like the loop back of a repetition, and those
+    // have to be tried before accepting, so that repetitions are greedy
+    fn accepting(&mut self, nfa: Nfa) -> Nfa {
+        let accept = NfaState {
+            id: self.next_id(),
+            transition: vec![],
+        };
+        let accept_id = accept.id;
+        let mut nfa = nfa;
+        for end_index in nfa.end.iter() {
+            let end_state = nfa.states.get_mut(end_index).unwrap();
+            end_state.transition.push((StateInput::Epsilon, accept_id));
+        }
+        nfa.states.insert_nfa_state(accept);
+        nfa.end = vec![accept_id];
+        nfa
+    }
+
     fn any_digit(&mut self) -> Nfa {
         let end = NfaState {
             id: self.next_id(),
@@ -365,14 +1101,15 @@ impl NfaBuilder {
         }
     }
 
-    fn any_char_in(&mut self, chars: Vec<char>) -> Nfa {
+    fn any_char_in(&mut self, chars: CharClass) -> Nfa {
+        let chars = self.fold_case(chars);
         let end = NfaState {
             id: self.next_id(),
             transition: vec![],
         };
         let start = NfaState {
             id: self.next_id(),
-            transition: vec![((StateInput::AnyCharIn(chars), end.id))],
+            transition: vec![(StateInput::AnyCharIn(chars), end.id)],
         };
         Nfa {
             start: start.id,
@@ -381,64 +1118,106 @@ impl NfaBuilder {
         }
     }
 
-    // create new start state and epsilon transition from the new start state to the left and right nfa start states
-    // All end states of the left and right nfa will be connected to the new end state
-    fn or(&mut self, left: Nfa, right: Nfa) -> Nfa {
-        let mut left = left;
-        let mut right = right;
+    // create new start state and epsilon transitions from the new start state to the start
+    // state of every alternative. All end states of the alternatives will be connected to the
+    // new end state
+    fn or(&mut self, nfas: Vec<Nfa>) -> Nfa {
         let end = NfaState {
             id: self.next_id(),
             transition: vec![],
         };
-        let start = NfaState {
+        let mut start = NfaState {
             id: self.next_id(),
-            transition: vec![
-                (StateInput::Epsilon, left.start),
-                (StateInput::Epsilon, right.start),
-            ],
+            transition: vec![],
         };
+        let mut states = HashMap::new();
+
+        for nfa in nfas {
+            let mut nfa = nfa;
+            start.transition.push((StateInput::Epsilon, nfa.start));
+            for end_index in nfa.end.iter() {
+                let end_state = nfa.states.get_mut(end_index).unwrap();
+                end_state.transition.push((StateInput::Epsilon, end.id));
+            }
+            states.extend(nfa.states);
+        }
 
         let start_id = start.id;
         let end_id = end.id;
+        states.insert_nfa_state(start);
+        states.insert_nfa_state(end);
 
-        for end_index in left.end.iter() {
-            let end_state = left.states.get_mut(end_index).unwrap();
-            end_state.transition.push((StateInput::Epsilon, end.id));
-        }
-        for end_index in right.end.iter() {
-            let end_state = right.states.get_mut(end_index).unwrap();
-            end_state.transition.push((StateInput::Epsilon, end.id));
+        Nfa {
+            start: start_id,
+            end: vec![end_id],
+            states,
         }
+    }
 
-        left.start = start_id;
-        left.end = vec![end_id];
-
-        left.states.extend(right.states);
-        left.states.insert_nfa_state(start);
-        left.states.insert_nfa_state(end);
+    // zero or more is one or more behind a new start state that can skip it. Adding the skip
+    // transitions to the existing start state instead would also allow skipping the rest of
+    // the nfa after looping back to that start state
+    fn kleene_star(&mut self, nfa: Nfa) -> Nfa {
+        let nfa = self.one_or_more(nfa);
+        self.optional(nfa)
+    }
 
-        left
+    fn optional(&mut self, nfa: Nfa) -> Nfa {
+        let empty = self.empty();
+        self.or(vec![nfa, empty])
     }
 
-    fn kleene_star(&mut self, nfa: Nfa) -> Nfa {
-        let mut nfa = nfa;
-        let start = nfa.start;
-        for end_index in nfa.end.iter() {
-            let end_state = nfa.states.get_mut(end_index).unwrap();
-            end_state.transition.push((StateInput::Epsilon, start));
+    // the first `min` copies of the nfa are required and the rest are optional, or the last
+    // copy is starred when there is no upper bound
+    fn repeat(&mut self, nfa: Nfa, min: usize, max: Option<usize>) -> Nfa {
+        let count = max.unwrap_or(min + 1);
+        if count == 0 {
+            return self.empty();
         }
-        let start_state = nfa.states.get_mut(&start).unwrap();
+        let mut copies: Vec<Nfa> = (1..count).map(|_| self.copy(&nfa)).collect();
+        copies.push(nfa);
 
-        for end_index in nfa.end.iter() {
-            start_state
-                .transition
-                .push((StateInput::Epsilon, *end_index));
+        let mut parts = vec![];
+        for (index, copy) in copies.into_iter().enumerate() {
+            if index < min {
+                parts.push(copy);
+            } else if max.is_none() {
+                parts.push(self.kleene_star(copy));
+            } else {
+                parts.push(self.optional(copy));
+            }
         }
+        self.concat(parts)
+    }
 
-        nfa
+    // clone of the nfa with fresh state ids, so it can be used next to the original
+    fn copy(&mut self, nfa: &Nfa) -> Nfa {
+        let ids: HashMap<StateId, StateId> =
+            nfa.states.keys().map(|&id| (id, self.next_id())).collect();
+        let states = nfa
+            .states
+            .values()
+            .map(|state| {
+                let copy = NfaState {
+                    id: ids[&state.id],
+                    transition: state
+                        .transition
+                        .iter()
+                        .map(|(input, next_state)| (input.clone(), ids[next_state]))
+                        .collect(),
+                };
+                (copy.id, copy)
+            })
+            .collect();
+        Nfa {
+            start: ids[&nfa.start],
+            end: nfa.end.iter().map(|id| ids[id]).collect(),
+            states,
+        }
     }
 
-    fn any_char_not_in(&mut self, chars: Vec<char>) -> Nfa {
+    fn any_char_not_in(&mut self, chars: CharClass) -> Nfa {
+        let chars = self.fold_case(chars);
         let end = NfaState {
             id: self.next_id(),
             transition: vec![],
@@ -466,30 +1245,43 @@ impl NfaBuilder {
     }
 
     fn sequence(&mut self, patterns: Vec<Pattern>) -> Nfa {
+        let nfas = patterns
+            .into_iter()
+            .map(|pattern| self.of(pattern))
+            .collect();
+        self.concat(nfas)
+    }
+
+    // matches the empty string
+    fn empty(&mut self) -> Nfa {
+        let mut states = HashMap::new();
+        let end = NfaState {
+            id: self.next_id(),
+            transition: vec![],
+        };
+        let start = NfaState {
+            id: self.next_id(),
+            transition: vec![(StateInput::Epsilon, end.id)],
+        };
+        let start_id = start.id;
+        let end_id = end.id;
+        states.insert_nfa_state(start);
+        states.insert_nfa_state(end);
+        Nfa {
+            start: start_id,
+            end: vec![end_id],
+            states,
+        }
+    }
+
+    fn concat(&mut self, nfas: Vec<Nfa>) -> Nfa {
+        if nfas.is_empty() {
+            return self.empty();
+        }
         let mut states = HashMap::new();
         let mut prev_end: Vec<usize> = vec![];
         let mut start: Option<usize> = None;
-        if patterns.len() == 0 {
-            let end = NfaState {
-                id: self.next_id(),
-                transition: vec![],
-            };
-            let start = NfaState {
-                id: self.next_id(),
-                transition: vec![(StateInput::Epsilon, end.id)],
-            };
-            let start_id = start.id;
-            let end_id = end.id;
-            states.insert_nfa_state(start);
-            states.insert_nfa_state(end);
-            return Nfa {
-                start: start_id,
-                end: vec![end_id],
-                states,
-            };
-        }
-        for pattern in patterns {
-            let next_nfa = self.of(pattern);
+        for next_nfa in nfas {
             if start.is_none() {
                 start = Some(next_nfa.start);
             }
@@ -523,104 +1315,370 @@ impl InsertNfaState for HashMap<StateId, NfaState> {
     }
 }
 
+// Simulates the nfa over a line, looking for a match at every position at once. Each thread
+// is an active state together with the position its match started at. Threads are kept in
+// priority order, which is the order a backtracking matcher would try them in: threads that
+// started earlier come first, and within one start the closure visits transitions in the order
+// the builder added them, so earlier alternatives and another round of a repetition come before
+// later alternatives and leaving the repetition.
 struct NfaRunner {
     nfa: Nfa,
-    current_states: Vec<StateId>,
+    match_kind: MatchKind,
+    // Matches may only start after a non-word character and are only accepted before one, as
+    // `-w` asks for. A rejected candidate doesn't end the search, which carries on with
+    // longer matches and later starts.
+    word: bool,
+    // When set, the active state set is written to stderr after every character.
+    trace: bool,
+    // Number of slots the `Save` transitions of the nfa write to.
+    slots: usize,
+}
+
+// How much of the leftmost match `NfaRunner::run` has to find before it stops.
+#[derive(Clone, Copy, PartialEq)]
+enum Until {
+    // Any match, when only whether there is one matters.
+    Earliest,
+    // Where the leftmost match starts.
+    Start,
+    // Where it starts and ends.
+    End,
 }
 
 impl NfaRunner {
-    fn new(nfa: Nfa) -> NfaRunner {
-        let start = nfa.start;
-        let mut current_states = vec![start];
-        NfaRunner::closure(&nfa.states, &mut current_states);
+    fn new(nfa: Nfa, match_kind: MatchKind, word: bool, trace: bool) -> NfaRunner {
+        let slots = nfa.slots();
         NfaRunner {
             nfa,
-            current_states,
+            match_kind,
+            word,
+            trace,
+            slots,
         }
     }
 
-    fn run(self, input: &str) -> bool {
-        let mut runner = self;
-        for c in input.chars() {
-            runner.next(c);
+    // The start of the leftmost match starting at or after `at`. The search stops as soon as
+    // that is known, which for a match that goes on to the end of the line can be long before
+    // its end.
+    fn leftmost_start(&self, input: &str, at: usize) -> Option<usize> {
+        self.run(input, at, Until::Start).map(|(start, _)| start)
+    }
+
+    // See `Matcher::find_at`. With `Until::Start` the end of the match returned is only the
+    // end of some match from that start.
+    fn run(&self, input: &str, at: usize, until: Until) -> Option<(usize, usize)> {
+        let mut threads: Vec<(StateId, usize)> = vec![];
+        let mut matched: Option<(usize, usize)> = None;
+        let mut chars = input[at..].char_indices().peekable();
+        loop {
+            let position = at + chars.peek().map_or(input.len() - at, |&(offset, _)| offset);
+            let word_before = input[..position]
+                .chars()
+                .next_back()
+                .is_some_and(is_word_char);
+            let word_after = input[position..].chars().next().is_some_and(is_word_char);
+            // Until there is a match, a new match may start here. It has the lowest priority.
+            if matched.is_none() && !(self.word && word_before) {
+                threads.push((self.nfa.start, position));
+            }
+            threads = self.closure(threads, position, input.len());
+
+            for (index, &(state_id, start)) in threads.iter().enumerate() {
+                if !self.nfa.end.contains(&state_id) || (self.word && word_after) {
+                    continue;
+                }
+                if until == Until::Earliest {
+                    return Some((start, position));
+                }
+                match self.match_kind {
+                    // Threads after this one would only be tried by a backtracking matcher if
+                    // this one failed, so they can't produce the reported match.
+                    MatchKind::LeftmostFirst => {
+                        matched = Some((start, position));
+                        threads.truncate(index);
+                        break;
+                    }
+                    // Keep looking for a longer match from the same start.
+                    MatchKind::LeftmostLongest => {
+                        if matched.filter(|&(best, _)| best < start).is_none() {
+                            matched = Some((start, position));
+                        }
+                    }
+                }
+            }
+            if let (MatchKind::LeftmostLongest, Some((best, _))) = (self.match_kind, matched) {
+                threads.retain(|&(_, start)| start <= best);
+            }
+            // Threads are in the order of their starts, so once the first one started with the
+            // match no match can start further left.
+            if let (Until::Start, Some((best, _))) = (until, matched) {
+                if !matches!(threads.first(), Some(&(_, start)) if start < best) {
+                    break;
+                }
+            }
+
+            let c = match chars.next() {
+                Some((_, c)) => c,
+                None => break,
+            };
+            if threads.is_empty() && matched.is_some() {
+                break;
+            }
+            threads = self.next(&threads, c);
         }
-        runner.is_match()
+        matched
     }
 
-    fn next(&mut self, c: char) {
+    fn next(&self, threads: &[(StateId, usize)], c: char) -> Vec<(StateId, usize)> {
         let states = &self.nfa.states;
         let mut new_states = vec![];
-        for state_index in &self.current_states {
-            let state = states.get(&state_index).unwrap();
+        for (state_index, start) in threads {
+            let state = states.get(state_index).unwrap();
             for (input, next_state) in state.transition.iter() {
-                match input {
-                    StateInput::Literal(literal) => {
-                        if *literal == c {
-                            new_states.push(*next_state);
-                        }
-                    }
-                    StateInput::AnyDigit => {
-                        if c.is_digit(10) {
-                            new_states.push(*next_state);
-                        }
-                    }
-                    StateInput::AnyChar => {
-                        new_states.push(*next_state);
+                let accepts = match input {
+                    StateInput::Literal(literal) => *literal == c,
+                    StateInput::AnyDigit => c.is_ascii_digit(),
+                    StateInput::AnyChar => true,
+                    StateInput::AnyCharIn(chars) => chars.contains(c),
+                    StateInput::AnyCharNotIn(chars) => !chars.contains(c),
+                    // eplison transitions and assertions are followed by the closure
+                    StateInput::Epsilon
+                    | StateInput::Start
+                    | StateInput::End
+                    | StateInput::Save(_) => false,
+                };
+                if accepts {
+                    new_states.push((*next_state, *start));
+                }
+            }
+        }
+        if self.trace {
+            let mut active: Vec<StateId> = new_states.iter().map(|&(id, _)| id).collect();
+            active.sort_unstable();
+            eprintln!("trace: {:?} -> {:?}", c, active);
+        }
+        new_states
+    }
+
+    // Adds every state reachable through epsilon transitions and through the assertions that
+    // hold at `position`. A state already reached by a thread of higher priority is skipped.
+    fn closure(
+        &self,
+        threads: Vec<(StateId, usize)>,
+        position: usize,
+        len: usize,
+    ) -> Vec<(StateId, usize)> {
+        let states = &self.nfa.states;
+        let mut seen = HashSet::new();
+        let mut closed = vec![];
+        for (state_id, start) in threads {
+            let mut stack = vec![state_id];
+            while let Some(current_state) = stack.pop() {
+                if !seen.insert(current_state) {
+                    continue;
+                }
+                closed.push((current_state, start));
+                let state = states.get(&current_state).unwrap();
+                for (input, next_state) in state.transition.iter().rev() {
+                    let follow = match input {
+                        StateInput::Epsilon | StateInput::Save(_) => true,
+                        StateInput::Start => position == 0,
+                        StateInput::End => position == len,
+                        _ => false,
+                    };
+                    if follow {
+                        stack.push(*next_state);
                     }
-                    StateInput::AnyCharIn(chars) => {
-                        if chars.contains(&c) {
-                            new_states.push(*next_state);
-                        }
+                }
+            }
+        }
+        closed
+    }
+}
+
+impl Matcher for NfaRunner {
+    fn find_at(&self, input: &str, at: usize, earliest: bool) -> Option<(usize, usize)> {
+        let until = if earliest {
+            Until::Earliest
+        } else {
+            Until::End
+        };
+        self.run(input, at, until)
+    }
+
+    fn captures_at(&self, input: &str, at: usize) -> Option<Vec<Option<usize>>> {
+        PikeVm { runner: self }.run(input, at)
+    }
+}
+
+type Slots = Vec<Option<usize>>;
+
+// A Pike VM: the simulation `NfaRunner` does, where every thread also carries slots with the
+// positions at which its path went through each `Save`. Threads are kept in the same priority
+// order, so the thread that produces the match also tells where each of its groups started and
+// ended. Copying the slots around makes this slower than `NfaRunner`, so it is only used when
+// the groups are asked for.
+struct PikeVm<'a> {
+    runner: &'a NfaRunner,
+}
+
+impl<'a> PikeVm<'a> {
+    fn run(&self, input: &str, at: usize) -> Option<Slots> {
+        let runner = self.runner;
+        let mut threads: Vec<(StateId, Slots)> = vec![];
+        let mut matched: Option<Slots> = None;
+        let mut chars = input[at..].char_indices().peekable();
+        loop {
+            let position = at + chars.peek().map_or(input.len() - at, |&(offset, _)| offset);
+            let word_before = input[..position]
+                .chars()
+                .next_back()
+                .is_some_and(is_word_char);
+            let word_after = input[position..].chars().next().is_some_and(is_word_char);
+            if matched.is_none() && !(runner.word && word_before) {
+                let mut slots = vec![None; runner.slots];
+                slots[0] = Some(position);
+                threads.push((runner.nfa.start, slots));
+            }
+            threads = self.closure(threads, position, input.len());
+
+            for index in 0..threads.len() {
+                let (state_id, slots) = &threads[index];
+                if !runner.nfa.end.contains(state_id) || (runner.word && word_after) {
+                    continue;
+                }
+                let start = slots[0];
+                let mut slots = slots.clone();
+                slots[1] = Some(position);
+                match runner.match_kind {
+                    MatchKind::LeftmostFirst => {
+                        matched = Some(slots);
+                        threads.truncate(index);
+                        break;
                     }
-                    StateInput::AnyCharNotIn(chars) => {
-                        if !chars.contains(&c) && c != SPECIAL_MARKER {
-                            new_states.push(*next_state);
+                    // Of the threads ending here with the same start, the first one has the
+                    // highest priority and picks the groups.
+                    MatchKind::LeftmostLongest => {
+                        let better = match &matched {
+                            None => true,
+                            Some(best) => {
+                                start < best[0] || (start == best[0] && best[1] < slots[1])
+                            }
+                        };
+                        if better {
+                            matched = Some(slots);
                         }
                     }
-                    StateInput::Epsilon => {
-                        // ignore eplison transitions, we will handle them later
-                    }
                 }
             }
+            if let (MatchKind::LeftmostLongest, Some(best)) = (runner.match_kind, &matched) {
+                threads.retain(|(_, slots)| slots[0] <= best[0]);
+            }
+
+            let c = match chars.next() {
+                Some((_, c)) => c,
+                None => break,
+            };
+            if threads.is_empty() && matched.is_some() {
+                break;
+            }
+            threads = self.next(threads, c);
         }
-        NfaRunner::closure(&self.nfa.states, &mut new_states);
-        self.current_states = new_states;
+        matched
     }
 
-    fn is_match(&self) -> bool {
-        for state_id in self.current_states.iter() {
-            if self.nfa.end.contains(state_id) {
-                return true;
+    fn next(&self, threads: Vec<(StateId, Slots)>, c: char) -> Vec<(StateId, Slots)> {
+        let states = &self.runner.nfa.states;
+        let mut new_threads = vec![];
+        for (state_id, slots) in threads {
+            for (input, next_state) in states[&state_id].transition.iter() {
+                let accepts = match input {
+                    StateInput::Literal(literal) => *literal == c,
+                    StateInput::AnyDigit => c.is_ascii_digit(),
+                    StateInput::AnyChar => true,
+                    StateInput::AnyCharIn(chars) => chars.contains(c),
+                    StateInput::AnyCharNotIn(chars) => !chars.contains(c),
+                    StateInput::Epsilon
+                    | StateInput::Start
+                    | StateInput::End
+                    | StateInput::Save(_) => false,
+                };
+                if accepts {
+                    new_threads.push((*next_state, slots.clone()));
+                }
             }
         }
-        false
+        new_threads
     }
 
-    fn closure(states: &HashMap<StateId, NfaState>, current: &mut Vec<usize>) {
-        let mut new_states = current.clone();
-        while new_states.len() > 0 {
-            let mut epsilon_transitons = vec![];
-            for current_state in new_states.iter() {
-                let state = states.get(&current_state).unwrap();
-                for (input, next_state) in state.transition.iter() {
-                    if let StateInput::Epsilon = input {
-                        epsilon_transitons.push(*next_state);
+    // `NfaRunner::closure`, recording the position in the slot of every `Save` followed.
+    fn closure(
+        &self,
+        threads: Vec<(StateId, Slots)>,
+        position: usize,
+        len: usize,
+    ) -> Vec<(StateId, Slots)> {
+        let states = &self.runner.nfa.states;
+        let mut seen = HashSet::new();
+        let mut closed = vec![];
+        for thread in threads {
+            let mut stack = vec![thread];
+            while let Some((current_state, slots)) = stack.pop() {
+                if !seen.insert(current_state) {
+                    continue;
+                }
+                for (input, next_state) in states[&current_state].transition.iter().rev() {
+                    match input {
+                        StateInput::Epsilon => stack.push((*next_state, slots.clone())),
+                        StateInput::Start if position == 0 => {
+                            stack.push((*next_state, slots.clone()))
+                        }
+                        StateInput::End if position == len => {
+                            stack.push((*next_state, slots.clone()))
+                        }
+                        StateInput::Save(slot) => {
+                            let mut slots = slots.clone();
+                            slots[*slot] = Some(position);
+                            stack.push((*next_state, slots));
+                        }
+                        _ => {}
                     }
                 }
+                closed.push((current_state, slots));
             }
-            new_states = NfaRunner::diff(&epsilon_transitons, &current);
-            current.extend(epsilon_transitons);
         }
+        closed
     }
+}
+
+// Iterates over the non-overlapping matches in a line. The search resumes where the previous
+// match ended; after an empty match it resumes one character later so it can't find the same
+// match forever, and an empty match right where the previous match ended is skipped.
+struct Matches<'a, 's> {
+    matcher: &'a dyn Matcher,
+    input: &'s str,
+    // Where the next search starts, or `None` once the end of the input was searched.
+    at: Option<usize>,
+    last_end: Option<usize>,
+}
+
+impl<'a, 's> Iterator for Matches<'a, 's> {
+    type Item = (usize, usize);
 
-    fn diff(a: &[usize], b: &[usize]) -> Vec<usize> {
-        let mut diff = vec![];
-        for a_item in a {
-            if !b.contains(a_item) {
-                diff.push(*a_item);
+    fn next(&mut self) -> Option<(usize, usize)> {
+        loop {
+            let (start, end) = self.matcher.find_at(self.input, self.at?, false)?;
+            if start == end {
+                self.at = self.input[end..].chars().next().map(|c| end + c.len_utf8());
+                if self.last_end == Some(end) {
+                    continue;
+                }
+            } else {
+                self.at = Some(end);
             }
+            self.last_end = Some(end);
+            return Some((start, end));
         }
-        diff
     }
 }
 
@@ -628,13 +1686,17 @@ impl NfaRunner {
 mod test {
     use super::*;
 
+    fn options(patterns: &[&str]) -> Options {
+        Options {
+            patterns: patterns.iter().map(|pattern| pattern.to_string()).collect(),
+            ..Options::default()
+        }
+    }
+
     fn test_grep(pattern: &str, input: &str, expected: bool) {
-        let grep = Grep {
-            pattern: pattern.to_string(),
-            input: input.to_string(),
-        };
+        let grep = Grep::new(&options(&[pattern])).unwrap();
         assert_eq!(
-            grep.is_match(),
+            grep.is_match(input),
             expected,
             "pattern: {}, input: {}",
             pattern,
@@ -673,5 +1735,294 @@ mod test {
         test_grep("^dog$", "dog", true);
         test_grep("ca+ts", "caaaats", true);
         test_grep("[^anb]", "banana", false);
+        test_grep("|dog", "dog", true);
+        test_grep("x|", "", true);
+        test_grep("*b", "a*b", true);
+        test_grep("*b", "b", false);
+        test_grep("a|+b", "+b", true);
+        test_grep("(?)", "?", true);
+    }
+
+    #[test]
+    fn grep_nested_groups() {
+        test_grep("((a|b)c)", "bc", true);
+        test_grep("((a|b)c)", "ab", false);
+        test_grep("x((a|b)(c|d))+y", "xacbdy", true);
+        test_grep("x((a|b)(c|d))+y", "xacby", false);
+        test_grep("^(((((a)))))$", "a", true);
+        test_grep("^(a(b(c(d)?)?)?)$", "abc", true);
+        test_grep("^(a(b(c(d)?)?)?)$", "abd", false);
+        test_grep("()", "", true);
+    }
+
+    #[test]
+    fn grep_alternation() {
+        test_grep("(a|b|c)", "c", true);
+        test_grep("^(cat|dog|bird)s?$", "birds", true);
+        test_grep("^(cat|dog|bird)s?$", "cow", false);
+        test_grep("^cat|dog$", "cats", true);
+        test_grep("^cat|dog$", "hotdog", true);
+        test_grep("^cat|dog$", "a cat", false);
+        test_grep("ab|cd", "xcdx", true);
+        test_grep("ab|cd", "acbd", false);
+        test_grep("a)", "a)", true);
+        test_grep("^(a|)$", "", true);
+        test_grep("^(|a)b$", "b", true);
+        test_grep("^x|$", "", true);
+        test_grep("^(|a|)$", "a", true);
+    }
+
+    #[test]
+    fn grep_optional() {
+        test_grep("^colou?r$", "color", true);
+        test_grep("^colou?r$", "colour", true);
+        test_grep("^colou?r$", "colouur", false);
+        test_grep("^(ab)?c$", "ababc", false);
+        test_grep("^(a+b)*$", "a", false);
+        test_grep("^(a+b)*$", "aabab", true);
+    }
+
+    #[test]
+    fn grep_counted_repetition() {
+        test_grep("^a{3}$", "aaa", true);
+        test_grep("^a{3}$", "aa", false);
+        test_grep("^a{3}$", "aaaa", false);
+        test_grep("^a{2,}$", "aaaaa", true);
+        test_grep("^a{2,}$", "a", false);
+        test_grep("^(ab){1,2}$", "abab", true);
+        test_grep("^(ab){1,2}$", "ababab", false);
+        test_grep("^x{,2}y$", "y", true);
+        test_grep("^a{0}b$", "b", true);
+        test_grep("a{", "a{", true);
+        test_grep(r"^\d{3}-\d{4}$", "555-1234", true);
+    }
+
+    #[test]
+    fn grep_bracket_expressions() {
+        test_grep("^[a-z]+$", "hello", true);
+        test_grep("^[a-z]+$", "a-z", false);
+        test_grep("^[a-]+$", "a-a", true);
+        test_grep("^[]a]+$", "]a]", true);
+        test_grep("^[^]a]$", "]", false);
+        test_grep("^[^]a]$", "b", true);
+        test_grep(r"^[\d.]+$", "3.14", true);
+        test_grep(r"^[\d.]+$", "d.", false);
+        test_grep(r"^[\w-]+$", "foo-bar_1", true);
+        test_grep(r"^[\]\\]+$", r"\]", true);
+        test_grep(r"^[^\s]+$", "tab\tstop", false);
+        test_grep("^[[:alpha:]_][[:alnum:]_]*$", "_tmp1", true);
+        test_grep("^[[:alpha:]_][[:alnum:]_]*$", "1tmp", false);
+        test_grep("^[[:upper:][:digit:]]+$", "AB12", true);
+        test_grep("[[:punct:]]", "no punctuation here", false);
+    }
+
+    #[test]
+    fn grep_ignore_case() {
+        let grep = Grep::new(&Options {
+            ignore_case: true,
+            ..options(&["^hello [a-c]+ [^x]$"])
+        })
+        .unwrap();
+        assert!(grep.is_match("HeLLo aBc y"));
+        assert!(!grep.is_match("HeLLo aBc X"));
+        assert!(!grep.is_match("HeLLo abd y"));
+
+        test_grep("(?i)straße", "STRASSE", false);
+        test_grep("(?i)straße", "STRAẞE", true);
+        test_grep("(?i)σ", "Σ", true);
+        test_grep("a(?i)b", "aB", true);
+        test_grep("a(?i)b", "AB", false);
+        test_grep("^(x(?i)y|z)z$", "xYz", true);
+        test_grep("^(x(?i)y|z)z$", "xyZ", false);
+        test_grep("^((?i)a|b)$", "B", true);
+        test_grep(r"(?i)[\W]", "k", false);
+        test_grep(r"(?i)[\S]", "ſ", true);
+        let grep = Grep::new(&Options {
+            ignore_case: true,
+            ..options(&[r"[\W\d]"])
+        })
+        .unwrap();
+        assert!(!grep.is_match("s"));
+        assert!(grep.is_match("-"));
+    }
+
+    fn find(pattern: &str, input: &str) -> Option<(usize, usize)> {
+        Grep::new(&options(&[pattern])).unwrap().find(input)
+    }
+
+    fn find_longest(pattern: &str, input: &str) -> Option<(usize, usize)> {
+        let grep = Grep::new(&Options {
+            posix: true,
+            ..options(&[pattern])
+        })
+        .unwrap();
+        grep.find(input)
+    }
+
+    #[test]
+    fn grep_find_leftmost_first() {
+        assert_eq!(find("b+c", "abbbc"), Some((1, 5)));
+        assert_eq!(find("(a|xb)c", "xxbcac"), Some((1, 4)));
+        assert_eq!(find("^a", "ab"), Some((0, 1)));
+        assert_eq!(find("b$", "abb"), Some((2, 3)));
+        assert_eq!(find("$", "ab"), Some((2, 2)));
+        assert_eq!(find("é+x", "aééx"), Some((1, 6)));
+        assert_eq!(find("x", "aéx"), Some((3, 4)));
+        assert_eq!(find("z", "abc"), None);
+        assert_eq!(find("a*", "baa"), Some((0, 0)));
+        assert_eq!(find("a+", "baaa"), Some((1, 4)));
+        assert_eq!(find("a|ab", "xab"), Some((1, 2)));
+        assert_eq!(find("(a|ab)(c|bcd)", "abcd"), Some((0, 4)));
+        assert_eq!(find("a{2,3}", "aaaa"), Some((0, 3)));
+        assert_eq!(find("a?", "a"), Some((0, 1)));
+    }
+
+    #[test]
+    fn grep_find_iter() {
+        let find_all = |pattern: &str, input: &str| -> Vec<(usize, usize)> {
+            let grep = Grep::new(&options(&[pattern])).unwrap();
+            grep.find_iter(input).collect()
+        };
+        assert_eq!(find_all("a+", "aabaaa"), vec![(0, 2), (3, 6)]);
+        assert_eq!(find_all("x*", "ab"), vec![(0, 0), (1, 1), (2, 2)]);
+        assert_eq!(find_all("a*", "baac"), vec![(0, 0), (1, 3), (4, 4)]);
+        assert_eq!(find_all("^a", "aaa"), vec![(0, 1)]);
+        assert_eq!(find_all("é*", "éxé"), vec![(0, 2), (3, 5)]);
+        assert_eq!(find_all("z", "abc"), vec![]);
+    }
+
+    #[test]
+    fn grep_whole_words_and_lines() {
+        let find_with =
+            |args: &[&str], input: &str| Grep::new(&cli::test_options(args)).unwrap().find(input);
+        assert_eq!(find_with(&["-w", "foo"], "foobar foo"), Some((7, 10)));
+        assert_eq!(find_with(&["-w", "foo"], "foo_ xfoo"), None);
+        assert_eq!(find_with(&["-w", "a|ab"], "ab"), Some((0, 2)));
+        assert_eq!(find_with(&["-w", "b+"], "abb bb"), Some((4, 6)));
+        assert_eq!(find_with(&["-w", "é"], "aé é"), Some((4, 6)));
+        assert_eq!(find_with(&["-x", "a|ab"], "ab"), Some((0, 2)));
+        assert_eq!(find_with(&["-x", "ab"], "abc"), None);
+        assert_eq!(find_with(&["-xw", "a b"], "a b"), Some((0, 3)));
+    }
+
+    #[test]
+    fn grep_pattern_lists() {
+        let grep = Grep::new(&options(&["cat", "d(o|u)g", "x\nbird"])).unwrap();
+        assert!(grep.is_match("hotdog") && grep.is_match("a bird") && grep.is_match("x"));
+        assert!(!grep.is_match("cow"));
+        assert_eq!(grep.find("a dug cat"), Some((2, 5)));
+
+        let grep = Grep::new(&options(&["cat", ""])).unwrap();
+        assert!(grep.is_match("cow") && grep.is_match(""));
+
+        let grep = Grep::new(&options(&[])).unwrap();
+        assert!(!grep.is_match("cow") && !grep.is_match(""));
+    }
+
+    #[test]
+    fn grep_fixed_strings() {
+        let grep_with = |args: &[&str]| Grep::new(&cli::test_options(args)).unwrap();
+        let grep = grep_with(&["-F", "a.b*"]);
+        assert_eq!(grep.find("aab a.b*"), Some((4, 8)));
+        assert!(!grep.is_match("a.bb"));
+
+        let grep = grep_with(&["-F", "-e", "(x)", "-e", "[y]"]);
+        assert_eq!(
+            grep.find_iter("[y] (x)").collect::<Vec<_>>(),
+            vec![(0, 3), (4, 7)]
+        );
+
+        assert!(grep_with(&["-Fi", "A.B"]).is_match("xa.by"));
+        assert!(!grep_with(&["-Fx", "a.b"]).is_match("xa.b"));
+        assert!(grep_with(&["-Fx", "a.b"]).is_match("a.b"));
+        assert!(!grep_with(&["-Fw", "a.b"]).is_match("a.bc"));
+    }
+
+    #[test]
+    fn grep_find_leftmost_longest() {
+        assert_eq!(find_longest("a|ab", "xab"), Some((1, 3)));
+        assert_eq!(find_longest("(a|ab)(c|bcd)", "abcd"), Some((0, 4)));
+        assert_eq!(find_longest("x*|b+", "bbb"), Some((0, 3)));
+        assert_eq!(find_longest("b|abc|ab", "zabcd"), Some((1, 4)));
+        assert_eq!(find_longest("z", "abc"), None);
+    }
+
+    #[test]
+    fn grep_capture_groups() {
+        let captures =
+            |patterns: &[&str], input: &str| Grep::new(&options(patterns)).unwrap().captures(input);
+        assert_eq!(
+            captures(&["(a+)(?:b)(?<x>c)?"], "zaab"),
+            Some(vec![Some((1, 4)), Some((1, 3)), None])
+        );
+        assert_eq!(
+            captures(&["(a|ab)(c|bcd)(d*)"], "abcd"),
+            Some(vec![Some((0, 4)), Some((0, 1)), Some((1, 4)), Some((4, 4))])
+        );
+        assert_eq!(
+            captures(&["(a)*"], "aaa"),
+            Some(vec![Some((0, 3)), Some((2, 3))])
+        );
+        assert_eq!(captures(&["(x)"], "abc"), None);
+        assert_eq!(
+            captures(&["(a)", "(?P<b>b)"], "xb"),
+            Some(vec![Some((1, 2)), None, Some((1, 2))])
+        );
+
+        let grep = Grep::new(&Options {
+            syntax: Syntax::Basic,
+            ..options(&[r"\(a\)\(b*\)"])
+        })
+        .unwrap();
+        assert_eq!(grep.describe_groups("xab"), "0=1..3 1=1..2 2=2..3");
+        let grep = Grep::new(&options(&["(?<word>a)(z)?"])).unwrap();
+        assert_eq!(grep.describe_groups("a"), "0=0..1 word=0..1 2=-");
+        assert_eq!(grep.describe_groups("b"), "no match");
+
+        let longest = Grep::new(&Options {
+            posix: true,
+            ..options(&["(a|ab)(c|bcd)?"])
+        })
+        .unwrap();
+        assert_eq!(
+            longest.captures("abcd"),
+            Some(vec![Some((0, 4)), Some((0, 1)), Some((1, 4))])
+        );
+    }
+
+    fn parse_error(pattern: &str) -> ParseError {
+        let chars: Vec<char> = pattern.chars().collect();
+        PatternParser::new(&chars, false).parse().unwrap_err()
+    }
+
+    #[test]
+    fn parse_errors_report_offsets() {
+        assert_eq!(parse_error(r"ab\"), ParseError::TrailingBackslash(2));
+        assert_eq!(parse_error("a[bc"), ParseError::UnterminatedClass(1));
+        assert_eq!(parse_error("x(a|b"), ParseError::UnmatchedParen(1));
+        assert_eq!(parse_error("a{2,1}"), ParseError::InvalidInterval(1));
+        assert_eq!(parse_error("a{2"), ParseError::InvalidInterval(1));
+        assert_eq!(parse_error("ab{1001}"), ParseError::RepetitionTooLarge(2));
+        assert_eq!(
+            parse_error("(a{1000}){1000}"),
+            ParseError::RepetitionTooLarge(9)
+        );
+        assert_eq!(
+            parse_error("((ab{10}){100}){1000}"),
+            ParseError::RepetitionTooLarge(15)
+        );
+        assert_eq!(parse_error("[]"), ParseError::UnterminatedClass(0));
+        assert_eq!(parse_error("a[z-a]"), ParseError::InvalidRange(2));
+        assert_eq!(parse_error(r"[a-\d]"), ParseError::InvalidRange(1));
+        assert_eq!(parse_error("[[:alfa:]]"), ParseError::InvalidClassName(1));
+        assert_eq!(parse_error("a(?<1x>b)"), ParseError::InvalidGroupName(1));
+        assert_eq!(parse_error("(?P<x"), ParseError::InvalidGroupName(0));
+        assert_eq!(parse_error(r"(a)\2"), ParseError::InvalidBackReference(3));
+
+        let err = PatternError {
+            pattern: "a[bc".to_string(),
+            error: parse_error("a[bc"),
+        };
+        assert_eq!(err.caret_diagnostic(), "a[bc\n ^");
     }
 }
@@ -0,0 +1,616 @@
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, Seek, SeekFrom, Write};
+use std::os::fd::AsFd;
+use std::path::Path;
+
+use crate::cli::{ListFiles, Options};
+use crate::color::Colors;
+use crate::Grep;
+
+// Decides which lines of an input are selected and writes them out. The matcher only says
+// whether a line matches; everything that depends on how lines are selected and reported,
+// such as inverting the match, lives here.
+pub struct Searcher<'a, W: Write> {
+    grep: &'a Grep,
+    out: W,
+    invert: bool,
+    // Print the number of selected lines instead of the lines themselves.
+    count: bool,
+    // Precede selected lines with their 1-based line number, the 1-based column of the first
+    // match and the byte offset of the line in the input.
+    line_number: bool,
+    column: bool,
+    byte_offset: bool,
+    // Print every non-empty match on its own line instead of the whole line. The column and
+    // byte offset are then those of the match.
+    only_matching: bool,
+    // Lines of context to print before and after every selected line.
+    before_context: usize,
+    after_context: usize,
+    // Whether a group of lines has been written, so the next group in any input is preceded
+    // by a `--` separator.
+    wrote_group: bool,
+    // Print the names of inputs instead of their lines, or nothing at all with `quiet`. Either
+    // way, reading an input stops at its first selected line.
+    list_files: Option<ListFiles>,
+    quiet: bool,
+    // Stop reading an input after this many selected lines and their trailing context.
+    max_count: Option<u64>,
+    colors: Colors,
+    // Write the spans of the capture groups in every selected line to stderr.
+    debug_groups: bool,
+}
+
+impl<'a, W: Write> Searcher<'a, W> {
+    pub fn new(grep: &'a Grep, options: &Options, colors: Colors, out: W) -> Searcher<'a, W> {
+        let summary = options.quiet || options.list_files.is_some();
+        // Context is only printed around whole lines.
+        let context = !(summary || options.count || options.only_matching);
+        Searcher {
+            grep,
+            out,
+            invert: options.invert,
+            count: options.count && !summary,
+            line_number: options.line_number,
+            column: options.column,
+            byte_offset: options.byte_offset,
+            only_matching: options.only_matching,
+            before_context: match context {
+                true => options.before_context.unwrap_or(options.context),
+                false => 0,
+            },
+            after_context: match context {
+                true => options.after_context.unwrap_or(options.context),
+                false => 0,
+            },
+            wrote_group: false,
+            list_files: options.list_files,
+            quiet: options.quiet,
+            max_count: options.max_count,
+            colors,
+            debug_groups: options.debug.groups,
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    // Searches the file at `path`, or standard input for `-`, and lists it under `name` if
+    // `list_files` asks for it.
+    pub fn search_path(
+        &mut self,
+        path: &Path,
+        name: &str,
+        prefix: Option<&str>,
+    ) -> io::Result<u64> {
+        let selected = if path == Path::new("-") {
+            self.search_stdin(prefix)?
+        } else {
+            // Reading a directory fails with EISDIR, which is the diagnostic we want.
+            let file = File::open(path)?;
+            self.search(io::BufReader::new(file), prefix)?
+        };
+        let listed = match self.list_files {
+            Some(ListFiles::WithMatches) => selected > 0,
+            Some(ListFiles::WithoutMatch) => selected == 0,
+            None => false,
+        };
+        if listed && !self.quiet {
+            let colors = &self.colors;
+            colors.paint(&mut self.out, &colors.file_name, name.as_bytes())?;
+            self.out.write_all(b"\n")?;
+        }
+        Ok(selected)
+    }
+
+    // When reading stops early because of `max_count`, whatever follows the last selected line
+    // is left for the next process reading the same standard input, as in `(grep -m1 x; cat)`,
+    // even the lines of trailing context written after it. The input is read in blocks and put
+    // back at the end of that line afterwards. Like GNU
+    // grep, this is only done for seekable inputs: a pipe would have to be read a byte at a
+    // time from the start, since whatever a block read takes past the last line can't be
+    // given back, and that would slow down every search that never reaches the limit.
+    fn search_stdin(&mut self, prefix: Option<&str>) -> io::Result<u64> {
+        if self.max_count.is_none() {
+            return self.search(io::stdin().lock(), prefix);
+        }
+        // A duplicate of the descriptor shares its file offset with standard input.
+        let mut file = File::from(io::stdin().as_fd().try_clone_to_owned()?);
+        match file.stream_position() {
+            Ok(start) => {
+                let (selected, consumed) = self.search_lines(io::BufReader::new(&file), prefix)?;
+                file.seek(SeekFrom::Start(start + consumed as u64))?;
+                Ok(selected)
+            }
+            Err(_) => self.search(io::BufReader::new(file), prefix),
+        }
+    }
+
+    // Reads the input one line at a time and writes every selected line, or their count with
+    // `count`, preceded by `prefix:` when one is given. Returns the number of selected lines.
+    pub fn search<R: BufRead>(&mut self, reader: R, prefix: Option<&str>) -> io::Result<u64> {
+        self.search_lines(reader, prefix)
+            .map(|(selected, _)| selected)
+    }
+
+    // Also returns the number of bytes up to the end of the last selected line once
+    // `max_count` lines are selected, and up to the end of the last line looked at otherwise.
+    // Only the lines of leading context are kept in memory, in a ring buffer that drops the
+    // oldest line once it holds `before_context` of them.
+    fn search_lines<R: BufRead>(
+        &mut self,
+        mut reader: R,
+        prefix: Option<&str>,
+    ) -> io::Result<(u64, usize)> {
+        let mut selected = 0;
+        let mut line_number = 0;
+        let mut offset = 0;
+        let mut selected_end = 0;
+        let mut buf = Vec::new();
+        let mut before: VecDeque<(usize, usize, Vec<u8>)> = VecDeque::new();
+        // Lines of trailing context still to be written after the last selected line.
+        let mut after = 0;
+        let mut last_written = None;
+        loop {
+            // After the last selected line only its trailing context is still read, and lines
+            // that would be selected are written as context.
+            let done = self.max_count.filter(|&max| selected >= max).is_some();
+            if done && after == 0 {
+                break;
+            }
+            buf.clear();
+            let read = reader.read_until(b'\n', &mut buf)?;
+            if read == 0 {
+                break;
+            }
+            line_number += 1;
+            let line = buf.strip_suffix(b"\n").unwrap_or(&buf);
+            let decoded = Decoded::new(line);
+            let is_selected = !done && self.grep.is_match(&decoded.text) != self.invert;
+            self.check_gave_up(line_number)?;
+            if is_selected {
+                selected += 1;
+                selected_end = offset + read;
+                if self.debug_groups && !self.invert {
+                    eprintln!("groups: {}", self.grep.describe_groups(&decoded.text));
+                }
+                if self.quiet || self.list_files.is_some() {
+                    offset += read;
+                    break;
+                }
+                for (number, offset, line) in before.drain(..) {
+                    self.write_context(prefix, number, offset, &line, &mut last_written)?;
+                }
+                if self.count {
+                    // only the total is printed
+                } else if self.only_matching {
+                    self.write_matches(prefix, line_number, line, &decoded, offset)?;
+                } else {
+                    let column = match self.column {
+                        true => self
+                            .grep
+                            .find(&decoded.text)
+                            .map(|(start, _)| decoded.raw(start) + 1),
+                        false => None,
+                    };
+                    self.write_separator(line_number, last_written)?;
+                    self.write_head(prefix, line_number, column, offset, b":")?;
+                    self.write_line(line, false)?;
+                    last_written = Some(line_number);
+                }
+                after = self.after_context;
+            } else if after > 0 {
+                self.write_context(prefix, line_number, offset, line, &mut last_written)?;
+                after -= 1;
+            } else if self.before_context > 0 {
+                if before.len() == self.before_context {
+                    before.pop_front();
+                }
+                before.push_back((line_number, offset, line.to_vec()));
+            }
+            self.check_gave_up(line_number)?;
+            offset += read;
+        }
+        if self.count {
+            if let Some(prefix) = prefix {
+                self.write_field(|colors| &colors.file_name, prefix, b":")?;
+            }
+            writeln!(self.out, "{}", selected)?;
+        }
+        let consumed = match self.max_count {
+            Some(max) if selected >= max => selected_end,
+            _ => offset,
+        };
+        Ok((selected, consumed))
+    }
+
+    // A line the matcher gave up on may or may not match, so the search can't go on past it.
+    // The matcher only gives up once a line would take it more time or memory than it allows.
+    fn check_gave_up(&self, line_number: usize) -> io::Result<()> {
+        if self.grep.gave_up() {
+            let message = format!("line {}: gave up matching backreferences", line_number);
+            return Err(io::Error::new(io::ErrorKind::OutOfMemory, message));
+        }
+        Ok(())
+    }
+
+    // Writes each non-empty match in `line` on a line of its own, as found in its decoded
+    // text. Lines selected by `invert` have no matches, so nothing is written for them.
+    fn write_matches(
+        &mut self,
+        prefix: Option<&str>,
+        line_number: usize,
+        line: &[u8],
+        decoded: &Decoded,
+        offset: usize,
+    ) -> io::Result<()> {
+        for (start, end) in self.grep.find_iter(&decoded.text) {
+            if start == end {
+                continue;
+            }
+            let (start, end) = (decoded.raw(start), decoded.raw(end));
+            self.write_head(prefix, line_number, Some(start + 1), offset + start, b":")?;
+            let colors = &self.colors;
+            colors.paint(&mut self.out, &colors.selected_match, &line[start..end])?;
+            self.out.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+
+    // Writes a line of context, separated from its fields by `-` instead of `:`.
+    fn write_context(
+        &mut self,
+        prefix: Option<&str>,
+        line_number: usize,
+        offset: usize,
+        line: &[u8],
+        last_written: &mut Option<usize>,
+    ) -> io::Result<()> {
+        self.write_separator(line_number, *last_written)?;
+        self.write_head(prefix, line_number, None, offset, b"-")?;
+        self.write_line(line, true)?;
+        *last_written = Some(line_number);
+        Ok(())
+    }
+
+    // Writes `--` before a group of lines that doesn't continue the last one written, which
+    // includes the first group of an input after groups in previous inputs.
+    fn write_separator(
+        &mut self,
+        line_number: usize,
+        last_written: Option<usize>,
+    ) -> io::Result<()> {
+        if self.before_context == 0 && self.after_context == 0 {
+            return Ok(());
+        }
+        let gap = match last_written {
+            Some(last) => line_number > last + 1,
+            None => self.wrote_group,
+        };
+        if gap {
+            let colors = &self.colors;
+            colors.paint(&mut self.out, &colors.separator, b"--")?;
+            self.out.write_all(b"\n")?;
+        }
+        self.wrote_group = true;
+        Ok(())
+    }
+
+    // Writes whichever of `prefix`, `line_number`, `column` and `offset` are enabled, each
+    // followed by `separator`. Inverted matches select lines without a match, which have no
+    // column.
+    fn write_head(
+        &mut self,
+        prefix: Option<&str>,
+        line_number: usize,
+        column: Option<usize>,
+        offset: usize,
+        separator: &[u8],
+    ) -> io::Result<()> {
+        if let Some(prefix) = prefix {
+            self.write_field(|colors| &colors.file_name, prefix, separator)?;
+        }
+        if self.line_number {
+            let line_number = line_number.to_string();
+            self.write_field(|colors| &colors.line_number, &line_number, separator)?;
+        }
+        if let (true, Some(column)) = (self.column, column) {
+            let column = column.to_string();
+            self.write_field(|colors| &colors.line_number, &column, separator)?;
+        }
+        if self.byte_offset {
+            let offset = offset.to_string();
+            self.write_field(|colors| &colors.byte_offset, &offset, separator)?;
+        }
+        Ok(())
+    }
+
+    // Writes `field` in the color `sgr` picks and the `separator` after it.
+    fn write_field(
+        &mut self,
+        sgr: fn(&Colors) -> &str,
+        field: &str,
+        separator: &[u8],
+    ) -> io::Result<()> {
+        let colors = &self.colors;
+        colors.paint(&mut self.out, sgr(colors), field.as_bytes())?;
+        colors.paint(&mut self.out, &colors.separator, separator)
+    }
+
+    // Writes a selected or context line with every non-empty match highlighted. Matches are
+    // found in the decoded line, but the bytes written are always those of the raw line.
+    fn write_line(&mut self, line: &[u8], context: bool) -> io::Result<()> {
+        let colors = &self.colors;
+        let (match_sgr, line_sgr) = match context {
+            true => (&colors.context_match, &colors.context_line),
+            false => (&colors.selected_match, &colors.selected_line),
+        };
+        if match_sgr.is_empty() && line_sgr.is_empty() {
+            self.out.write_all(line)?;
+            return self.out.write_all(b"\n");
+        }
+        let mut last = 0;
+        if !match_sgr.is_empty() {
+            let decoded = Decoded::new(line);
+            for (start, end) in self.grep.find_iter(&decoded.text) {
+                if start == end {
+                    continue;
+                }
+                let (start, end) = (decoded.raw(start), decoded.raw(end));
+                colors.paint(&mut self.out, line_sgr, &line[last..start])?;
+                colors.paint(&mut self.out, match_sgr, &line[start..end])?;
+                last = end;
+            }
+        }
+        colors.paint(&mut self.out, line_sgr, &line[last..])?;
+        self.out.write_all(b"\n")
+    }
+}
+
+// A line decoded for matching. `String::from_utf8_lossy` turns every invalid sequence into a
+// U+FFFD, which takes three bytes, so offsets in the text are mapped back to the raw line for
+// whatever is reported in bytes.
+struct Decoded<'a> {
+    text: Cow<'a, str>,
+    // Where each replacement is in the text and in the raw line, and how many raw bytes it
+    // stands for.
+    replaced: Vec<(usize, usize, usize)>,
+}
+
+impl<'a> Decoded<'a> {
+    fn new(line: &'a [u8]) -> Decoded<'a> {
+        let mut replaced = vec![];
+        let (mut text_at, mut raw_at) = (0, 0);
+        let mut rest = line;
+        while let Err(error) = std::str::from_utf8(rest) {
+            let valid = error.valid_up_to();
+            let len = error.error_len().unwrap_or(rest.len() - valid);
+            replaced.push((text_at + valid, raw_at + valid, len));
+            text_at += valid + REPLACEMENT_LEN;
+            raw_at += valid + len;
+            rest = &rest[valid + len..];
+        }
+        Decoded {
+            text: String::from_utf8_lossy(line),
+            replaced,
+        }
+    }
+
+    // The offset in the raw line of the character boundary at `offset` in the text.
+    fn raw(&self, offset: usize) -> usize {
+        match self
+            .replaced
+            .iter()
+            .take_while(|&&(text_at, _, _)| text_at < offset)
+            .last()
+        {
+            Some(&(text_at, raw_at, len)) => offset - (text_at + REPLACEMENT_LEN) + raw_at + len,
+            None => offset,
+        }
+    }
+}
+
+const REPLACEMENT_LEN: usize = char::REPLACEMENT_CHARACTER.len_utf8();
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cli::{test_options, ColorChoice};
+
+    fn search(args: &[&str], input: &str) -> (u64, String) {
+        let (selected, out) = search_bytes(args, input.as_bytes());
+        (selected, String::from_utf8(out).unwrap())
+    }
+
+    fn search_bytes(args: &[&str], input: &[u8]) -> (u64, Vec<u8>) {
+        let options = test_options(args);
+        let grep = Grep::new(&options).unwrap();
+        let colors = match options.color {
+            ColorChoice::Always => Colors::default(),
+            _ => Colors::plain(),
+        };
+        let mut searcher = Searcher::new(&grep, &options, colors, vec![]);
+        let selected = searcher.search(input, None).unwrap();
+        (selected, searcher.into_inner())
+    }
+
+    #[test]
+    fn search_every_line() {
+        let (selected, out) = search(&["dog$"], "cat\nhotdog\ndogs\ndog\n");
+        assert_eq!(selected, 2);
+        assert_eq!(out, "hotdog\ndog\n");
+
+        assert_eq!(search(&["dog$"], "cat\ndogs"), (0, String::new()));
+
+        let options = Options {
+            patterns: vec!["dog".to_string()],
+            ..Options::default()
+        };
+        let grep = Grep::new(&options).unwrap();
+        let mut searcher = Searcher::new(&grep, &options, Colors::plain(), vec![]);
+        searcher
+            .search("dog\n".as_bytes(), Some("pets.txt"))
+            .unwrap();
+        assert_eq!(searcher.into_inner(), b"pets.txt:dog\n");
+    }
+
+    #[test]
+    fn search_inverted() {
+        let (selected, out) = search(&["-v", "dog"], "cat\nhotdog\nbird\n");
+        assert_eq!(selected, 2);
+        assert_eq!(out, "cat\nbird\n");
+
+        assert_eq!(search(&["-v", "o"], "dog\ncow\n"), (0, String::new()));
+    }
+
+    #[test]
+    fn search_line_positions() {
+        let input = "cat\nhot dog\nbird\ndog\n";
+        assert_eq!(search(&["-n", "dog"], input).1, "2:hot dog\n4:dog\n");
+        assert_eq!(search(&["-b", "dog"], input).1, "4:hot dog\n17:dog\n");
+        assert_eq!(search(&["--column", "dog"], input).1, "5:hot dog\n1:dog\n");
+        assert_eq!(
+            search(&["-nb", "--column", "o"], input).1,
+            "2:2:4:hot dog\n4:2:17:dog\n"
+        );
+        assert_eq!(
+            search(&["-nv", "--column", "o"], input).1,
+            "1:cat\n3:bird\n"
+        );
+        // Columns count bytes of the input, not of its decoded text.
+        assert_eq!(
+            search_bytes(&["--column", "foo"], b"\xff\xfe foo\n").1,
+            b"4:\xff\xfe foo\n"
+        );
+    }
+
+    #[test]
+    fn search_only_matching() {
+        let input = "id=17 id=4\nnone\nx id=250\n";
+        let (selected, out) = search(&["-o", "[0-9]+"], input);
+        assert_eq!(selected, 2);
+        assert_eq!(out, "17\n4\n250\n");
+        assert_eq!(
+            search(&["-ob", "--column", "id=[0-9]+"], input).1,
+            "1:0:id=17\n7:6:id=4\n3:18:id=250\n"
+        );
+        assert_eq!(search(&["-o", "x*"], "abc\nxx\n"), (2, "xx\n".to_string()));
+        assert_eq!(search(&["-ov", "id"], input), (1, String::new()));
+
+        let input = b"\xff\xfe foo \xe2\x82x foo\n";
+        assert_eq!(search_bytes(&["-ob", "foo"], input).1, b"3:foo\n11:foo\n");
+        assert_eq!(
+            search_bytes(&["-o", ".*foo"], input).1,
+            b"\xff\xfe foo \xe2\x82x foo\n"
+        );
+    }
+
+    #[test]
+    fn search_colors() {
+        let (_, out) = search(&["--color=always", "-n", "o+"], "x\nfoo bo\n");
+        assert_eq!(
+            out,
+            "\x1b[32m\x1b[K2\x1b[m\x1b[K\x1b[36m\x1b[K:\x1b[m\x1b[K\
+             f\x1b[01;31m\x1b[Koo\x1b[m\x1b[K b\x1b[01;31m\x1b[Ko\x1b[m\x1b[K\n"
+        );
+        let (_, out) = search(&["--color=always", "-o", "o+"], "foo\n");
+        assert_eq!(out, "\x1b[01;31m\x1b[Koo\x1b[m\x1b[K\n");
+        assert_eq!(search(&["--color=auto", "o"], "foo\n").1, "foo\n");
+        let (_, out) = search_bytes(&["--color=always", "o+"], b"\xffoo\xfe\n");
+        assert_eq!(out, b"\xff\x1b[01;31m\x1b[Koo\x1b[m\x1b[K\xfe\n");
+    }
+
+    #[test]
+    fn search_context() {
+        let input = "a\nb\nmatch\nc\nd\ne\nf\nmatch\ng\nmatch\nh\n";
+        assert_eq!(
+            search(&["-n", "-C1", "match"], input).1,
+            "2-b\n3:match\n4-c\n--\n7-f\n8:match\n9-g\n10:match\n11-h\n"
+        );
+        assert_eq!(
+            search(&["-A", "1", "match"], input).1,
+            "match\nc\n--\nmatch\ng\nmatch\nh\n"
+        );
+        assert_eq!(
+            search(&["-B2", "-C0", "match"], input).1,
+            "a\nb\nmatch\n--\ne\nf\nmatch\ng\nmatch\n"
+        );
+        assert_eq!(
+            search(&["-C9", "-c", "match"], input),
+            (3, "3\n".to_string())
+        );
+
+        // Groups in different inputs are separated too.
+        let options = Options {
+            patterns: vec!["x".to_string()],
+            after_context: Some(1),
+            ..Options::default()
+        };
+        let grep = Grep::new(&options).unwrap();
+        let mut searcher = Searcher::new(&grep, &options, Colors::plain(), vec![]);
+        searcher.search("x\ny\n".as_bytes(), Some("1")).unwrap();
+        searcher.search("x\n".as_bytes(), Some("2")).unwrap();
+        assert_eq!(searcher.into_inner(), b"1:x\n1-y\n--\n2:x\n");
+    }
+
+    #[test]
+    fn search_summaries() {
+        assert_eq!(search(&["-q", "o"], "dog\ncow\n"), (1, String::new()));
+        assert_eq!(search(&["-l", "-c", "o"], "dog\ncow\n"), (1, String::new()));
+        assert_eq!(search(&["-L", "x"], "dog\ncow\n"), (0, String::new()));
+
+        let path = std::env::temp_dir().join(format!("search-list-{}", std::process::id()));
+        std::fs::write(&path, "dog\ncow\n").unwrap();
+        let list = |args: &[&str]| {
+            let options = test_options(args);
+            let grep = Grep::new(&options).unwrap();
+            let mut searcher = Searcher::new(&grep, &options, Colors::plain(), vec![]);
+            searcher.search_path(&path, "pets", None).unwrap();
+            String::from_utf8(searcher.into_inner()).unwrap()
+        };
+        assert_eq!(list(&["-l", "o"]), "pets\n");
+        assert_eq!(list(&["-l", "x"]), "");
+        assert_eq!(list(&["-L", "x"]), "pets\n");
+        assert_eq!(list(&["-lL", "o"]), "");
+        assert_eq!(list(&["-ql", "o"]), "");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn search_max_count() {
+        let input = "a1\nb\na2\na3\nc\n";
+        assert_eq!(search(&["-m2", "a"], input), (2, "a1\na2\n".to_string()));
+        assert_eq!(search(&["-m2", "-c", "a"], input), (2, "2\n".to_string()));
+        assert_eq!(search(&["-m1", "-v", "a"], input), (1, "b\n".to_string()));
+        assert_eq!(search(&["-m0", "a"], input), (0, String::new()));
+        assert_eq!(search(&["-m1", "-nA2", "a"], input).1, "1:a1\n2-b\n3-a2\n");
+
+        // The input is left right after the last selected line, not after its context.
+        let options = test_options(&["-m1", "-A1", "x"]);
+        let grep = Grep::new(&options).unwrap();
+        let mut searcher = Searcher::new(&grep, &options, Colors::plain(), vec![]);
+        let input = "x1\nctx\nx2\nrest\n";
+        assert_eq!(
+            searcher.search_lines(input.as_bytes(), None).unwrap(),
+            (1, 3)
+        );
+        assert_eq!(searcher.into_inner(), b"x1\nctx\n");
+    }
+
+    #[test]
+    fn search_count() {
+        assert_eq!(
+            search(&["-c", "o"], "dog\ncat\ncow\n"),
+            (2, "2\n".to_string())
+        );
+        assert_eq!(
+            search(&["-cv", "o"], "dog\ncat\ncow\n"),
+            (1, "1\n".to_string())
+        );
+        assert_eq!(search(&["--count", "x"], "dog\n"), (0, "0\n".to_string()));
+    }
+}
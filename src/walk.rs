@@ -0,0 +1,146 @@
+use std::fs;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+// A directory is identified by its device and inode numbers, which is what lets us notice
+// that a symlink leads back into one of the directories we are already inside of.
+type DirId = (u64, u64);
+
+#[derive(Debug)]
+pub enum Entry {
+    File(PathBuf),
+    Error(PathBuf, io::Error),
+    Loop(PathBuf),
+}
+
+struct Frame {
+    id: DirId,
+    // Stored in reverse order so the next entry can be popped off the end.
+    entries: Vec<PathBuf>,
+}
+
+// Depth-first walk over every regular file under a root. The root itself is always followed
+// even if it is a symlink; links found while walking are only followed with `follow_links`.
+pub struct Walk {
+    follow_links: bool,
+    root: Option<PathBuf>,
+    stack: Vec<Frame>,
+}
+
+impl Walk {
+    pub fn new(root: &Path, follow_links: bool) -> Walk {
+        Walk {
+            follow_links,
+            root: Some(root.to_path_buf()),
+            stack: vec![],
+        }
+    }
+
+    fn visit(&mut self, path: PathBuf, follow: bool) -> Option<Entry> {
+        let metadata = if follow {
+            fs::metadata(&path)
+        } else {
+            fs::symlink_metadata(&path)
+        };
+        let metadata = match metadata {
+            Ok(metadata) => metadata,
+            Err(err) => return Some(Entry::Error(path, err)),
+        };
+        if metadata.is_file() {
+            return Some(Entry::File(path));
+        }
+        if !metadata.is_dir() {
+            // Unfollowed symlinks, devices, fifos and sockets are skipped when recursing.
+            return None;
+        }
+
+        let id = (metadata.dev(), metadata.ino());
+        if self.stack.iter().any(|frame| frame.id == id) {
+            return Some(Entry::Loop(path));
+        }
+        let mut entries = match read_dir_sorted(&path) {
+            Ok(entries) => entries,
+            Err(err) => return Some(Entry::Error(path, err)),
+        };
+        entries.reverse();
+        self.stack.push(Frame { id, entries });
+        None
+    }
+}
+
+impl Iterator for Walk {
+    type Item = Entry;
+
+    fn next(&mut self) -> Option<Entry> {
+        if let Some(root) = self.root.take() {
+            if let Some(entry) = self.visit(root, true) {
+                return Some(entry);
+            }
+        }
+        loop {
+            let frame = self.stack.last_mut()?;
+            match frame.entries.pop() {
+                Some(path) => {
+                    let follow = self.follow_links;
+                    if let Some(entry) = self.visit(path, follow) {
+                        return Some(entry);
+                    }
+                }
+                None => {
+                    self.stack.pop();
+                }
+            }
+        }
+    }
+}
+
+fn read_dir_sorted(path: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut entries = vec![];
+    for entry in fs::read_dir(path)? {
+        entries.push(entry?.path());
+    }
+    entries.sort();
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("walk-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("a.txt"), "a").unwrap();
+        fs::write(dir.join("sub/b.txt"), "b").unwrap();
+        symlink(&dir, dir.join("sub/up")).unwrap();
+        dir
+    }
+
+    fn describe(dir: &Path, walk: Walk) -> Vec<String> {
+        walk.map(|entry| match entry {
+            Entry::File(path) => format!("file {}", path.strip_prefix(dir).unwrap().display()),
+            Entry::Loop(path) => format!("loop {}", path.strip_prefix(dir).unwrap().display()),
+            Entry::Error(path, err) => format!("error {}: {}", path.display(), err),
+        })
+        .collect()
+    }
+
+    #[test]
+    fn walk_skips_symlinks_unless_following() {
+        let dir = scratch_dir("skip");
+        let entries = describe(&dir, Walk::new(&dir, false));
+        assert_eq!(entries, vec!["file a.txt", "file sub/b.txt"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn walk_detects_symlink_loops() {
+        let dir = scratch_dir("loop");
+        let entries = describe(&dir, Walk::new(&dir, true));
+        assert_eq!(entries, vec!["file a.txt", "file sub/b.txt", "loop sub/up"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}